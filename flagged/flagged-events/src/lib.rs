@@ -0,0 +1,267 @@
+//! The events flagged publishes while it runs exploits, and a [`Subscriber`] to read them back.
+//!
+//! # Format
+//!
+//! Every event is a JSON object, one per line (JSONL files, Unix sockets) or message (redis pub/sub on the
+//! `events` channel, redis streams in the `event` field):
+//!
+//! ```json
+//! {"version":2,"session_id":"…","timestamp":"2022-05-28T12:00:03.14Z","payload":{"t":"RunExit","c":{"run":{…},"exit_code":0}}}
+//! ```
+//!
+//! The payload is tagged: `t` names the event type (an [`EventPayload`] variant), `c` holds its fields.
+//! Field names are snake_case, durations are seconds as floats, timestamps are RFC 3339 in UTC.
+//!
+//! # Compatibility
+//!
+//! [`SCHEMA_VERSION`] is bumped whenever an event type or field is added, removed, renamed or retyped, so every
+//! version has exactly one shape, listed below. This crate reads events of every earlier version: fields that
+//! older versions didn't send are `Option`s or have defaults. Consumers should still expect newer versions than
+//! they know; this crate reads event types added since as [`EventPayload::Unknown`] instead of failing.
+//!
+//! | Version | Changes |
+//! |---|---|
+//! | 1 | No `version` field. `IntervalStart` and `IntervalEnd` have no `c`, `FlagMatch` may lack `is_unique` |
+//! | 2 | Interval ids and indices, `SessionEnd`, `RunStats`, `RunSpawnFailed`, `RunKillFailed`, `SubmitBatch` |
+//! | 3 | `FlagDropped`. Expired and unanswered flags were `FlagVerdict`s starting with `[flagged]` before |
+
+use chrono::{DateTime, Utc};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+mod stream;
+mod subscriber;
+
+pub use stream::StreamSpec;
+pub use subscriber::{Error, Subscriber};
+
+/// Version of the event format flagged currently publishes, see the crate docs.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Sent once when flagged starts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionAnnouncement {
+    pub hostname: String,
+    /// Working directory of the exploit
+    pub path: String,
+    /// The exploit's effective configuration, as JSON
+    pub config: serde_json::Value,
+}
+
+/// One exploit execution against one target
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Run {
+    pub id: Uuid,
+    /// Identifies the target, by default its `IP` field
+    pub key: String,
+    pub target: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interval {
+    pub id: Uuid,
+    /// Starts at 1 for every session
+    pub index: u64,
+    pub round: Option<u64>,
+}
+
+/// Resources used by an exploit run, including its own (waited-for) children
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode, in seconds
+    pub user_time: f64,
+    /// CPU time spent in the kernel, in seconds
+    pub system_time: f64,
+    /// Peak resident set size in kilobytes
+    pub max_rss_kb: u64,
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+    /// See [`SCHEMA_VERSION`]. 1 if the event doesn't say
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+    /// Identifies one invocation of flagged
+    pub session_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub payload: EventPayload,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "t", content = "c", remote = "Self")]
+#[non_exhaustive]
+pub enum EventPayload {
+    SessionAnnouncement(Box<SessionAnnouncement>),
+    /// Sent when flagged stops running intervals. Missing if it crashed or was killed
+    SessionEnd {
+        intervals: u64,
+    },
+    /// `None` in version 1 recordings
+    IntervalStart(Option<Interval>),
+    IntervalEnd(Option<Interval>),
+    RunStart(Run),
+    /// The exploit couldn't be started. No other events are sent for this run
+    RunSpawnFailed {
+        run: Run,
+        error: String,
+    },
+    /// The exploit missed its deadline but couldn't be killed
+    RunKillFailed {
+        run: Run,
+        error: String,
+    },
+    RunTimeout(Run),
+    RunExit {
+        run: Run,
+        exit_code: Option<i32>,
+    },
+    StdoutLine {
+        run: Run,
+        line: String,
+    },
+    StderrLine {
+        run: Run,
+        line: String,
+    },
+    FlagMatch {
+        run: Run,
+        flag: String,
+        /// Missing in recordings of older versions
+        #[serde(default)]
+        is_unique: bool,
+    },
+    FlagPending {
+        run: Run,
+        flag: String,
+    },
+    /// The gameserver's verdict
+    FlagVerdict {
+        run: Run,
+        flag: String,
+        verdict: String,
+    },
+    /// flagged gave up on the flag without a verdict from the gameserver
+    FlagDropped {
+        run: Run,
+        flag: String,
+        /// `expired` if it was too old to be submitted, `unanswered` if the submitter returned no verdict
+        reason: String,
+    },
+    /// Sent after `RunExit` or `RunTimeout`
+    RunStats {
+        run: Run,
+        /// Seconds from spawning the exploit until it exited or was killed
+        wall_time: f64,
+        /// Missing if the exploit couldn't be waited for
+        rusage: Option<ResourceUsage>,
+    },
+    /// One attempt to submit a batch of flags
+    SubmitBatch {
+        flags: usize,
+        /// Flags that received a verdict in this attempt
+        verdicts: usize,
+        /// Seconds spent submitting
+        duration: f64,
+        /// The submitter's error, the flags without verdict are retried
+        error: Option<String>,
+    },
+    /// An event type added after this crate's version. Can't be serialized again
+    #[serde(other, skip_serializing)]
+    Unknown,
+}
+
+impl Serialize for EventPayload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        EventPayload::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EventPayload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut payload = serde_json::Value::deserialize(deserializer)?;
+        let err = match EventPayload::deserialize(&payload) {
+            Ok(payload) => return Ok(payload),
+            Err(err) => err,
+        };
+        // `Unknown` can't hold content, so unknown types are retried without it
+        if let Some(payload) = payload.as_object_mut() {
+            payload.remove("c");
+        }
+        match EventPayload::deserialize(&payload) {
+            Ok(EventPayload::Unknown) => Ok(EventPayload::Unknown),
+            _ => Err(D::Error::custom(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &str = "\"session_id\":\"6b7e3fb1-1c6a-4b0e-9a6e-2f1e4b1c9d3a\",\"timestamp\":\"2022-05-28T12:00:00Z\"";
+    const RUN: &str =
+        "{\"id\":\"6b7e3fb1-1c6a-4b0e-9a6e-2f1e4b1c9d3a\",\"key\":\"1.2.3.4\",\"target\":{}}";
+
+    fn parse(fields: &str) -> Event {
+        serde_json::from_str(&format!("{{{},{}}}", SESSION, fields)).unwrap()
+    }
+
+    #[test]
+    fn reads_version_1_events() {
+        let event = parse("\"payload\":{\"t\":\"IntervalStart\"}");
+        assert_eq!(event.version, 1);
+        assert!(matches!(event.payload, EventPayload::IntervalStart(None)));
+
+        let event = parse(&format!(
+            "\"payload\":{{\"t\":\"FlagMatch\",\"c\":{{\"run\":{},\"flag\":\"FLAG{{1}}\"}}}}",
+            RUN
+        ));
+        assert!(matches!(
+            event.payload,
+            EventPayload::FlagMatch {
+                is_unique: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn reads_unknown_event_types() {
+        for payload in [
+            "{\"t\":\"Teleport\"}",
+            "{\"t\":\"Teleport\",\"c\":null}",
+            "{\"t\":\"Teleport\",\"c\":{\"to\":\"mars\",\"eta\":[1,2]}}",
+            "{\"t\":\"Teleport\",\"c\":\"now\"}",
+        ]
+        .iter()
+        {
+            let event = parse(&format!("\"version\":9,\"payload\":{}", payload));
+            assert_eq!(event.version, 9);
+            assert!(
+                matches!(event.payload, EventPayload::Unknown),
+                "{}",
+                payload
+            );
+        }
+        assert!(serde_json::to_string(&EventPayload::Unknown).is_err());
+        // known types still have to be valid
+        assert!(serde_json::from_str::<EventPayload>("{\"t\":\"RunExit\",\"c\":{}}").is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        let event = parse(&format!(
+            "\"version\":2,\"payload\":{{\"t\":\"RunStats\",\"c\":{{\"run\":{},\"wall_time\":1.5,\"rusage\":{{\"user_time\":0.25,\"system_time\":0.5,\"max_rss_kb\":2048}}}}}}",
+            RUN
+        ));
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["payload"]["c"]["rusage"]["max_rss_kb"], 2048);
+        assert_eq!(json["version"], 2);
+    }
+}
@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+const DEFAULT_KEY: &str = "events";
+const DEFAULT_MAXLEN: usize = 1_000_000;
+const DEFAULT_FROM: &str = "0";
+
+/// A redis stream as given to `flagged --events` and [`Subscriber::open`](crate::Subscriber::open):
+/// `redis-stream://host[:port][/db][?key=events&maxlen=1000000&from=0]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamSpec {
+    /// The server, as a `redis://` url
+    pub url: String,
+    /// Key of the stream
+    pub key: String,
+    /// Publishers trim the stream to roughly this many entries
+    pub maxlen: usize,
+    /// Subscribers start after this entry id: `0` for the oldest entry, `$` for new entries only
+    pub from: String,
+}
+
+impl FromStr for StreamSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let rest = spec
+            .strip_prefix("redis-stream://")
+            .ok_or_else(|| format!("{:?} doesn't start with redis-stream://", spec))?;
+        let (addr, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let url = format!("redis://{}", addr);
+        if redis::parse_redis_url(&url).is_none() {
+            return Err(format!("invalid redis url {:?}", url));
+        }
+        let mut stream = StreamSpec {
+            url,
+            key: DEFAULT_KEY.to_string(),
+            maxlen: DEFAULT_MAXLEN,
+            from: DEFAULT_FROM.to_string(),
+        };
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("key", value)) => stream.key = value.to_string(),
+                Some(("maxlen", value)) => {
+                    stream.maxlen = value
+                        .parse()
+                        .map_err(|_| format!("invalid maxlen {:?}", value))?
+                }
+                Some(("from", value)) => stream.from = value.to_string(),
+                _ => return Err(format!("unknown redis-stream parameter {:?}", param)),
+            }
+        }
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stream_specs() {
+        assert_eq!(
+            "redis-stream://localhost:6380/2?maxlen=10&key=ev&from=$".parse(),
+            Ok(StreamSpec {
+                url: "redis://localhost:6380/2".into(),
+                key: "ev".into(),
+                maxlen: 10,
+                from: "$".into(),
+            })
+        );
+        let stream = "redis-stream://localhost".parse::<StreamSpec>().unwrap();
+        assert_eq!(
+            (&*stream.key, stream.maxlen, &*stream.from),
+            ("events", 1_000_000, "0")
+        );
+        assert!("redis-stream://localhost?len=10"
+            .parse::<StreamSpec>()
+            .is_err());
+        assert!("redis-stream://localhost?maxlen=many"
+            .parse::<StreamSpec>()
+            .is_err());
+        assert!("redis://localhost".parse::<StreamSpec>().is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{Event, StreamSpec};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// The pub/sub channel flagged publishes to
+const CHANNEL: &str = "events";
+// Stream entries fetched per request
+const STREAM_BATCH_SIZE: usize = 100;
+
+/// `XREAD` replies: entries (id and field-value pairs) per stream, `nil` if there were none
+type StreamReply = Option<Vec<(String, Vec<(String, Vec<(String, String)>)>)>>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The source couldn't be opened or read. The subscriber ends afterwards
+    Io(io::Error),
+    /// Redis failed. The subscriber ends afterwards
+    Redis(redis::RedisError),
+    /// A line or message isn't a valid event. The subscriber continues with the next one
+    Decode {
+        /// Line (or message) number, starting at 1
+        line: usize,
+        error: serde_json::Error,
+    },
+    /// The source spec passed to [`Subscriber::open`] is invalid
+    Spec(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Redis(err) => write!(f, "redis: {}", err),
+            Error::Decode { line, error } => write!(f, "{}: skipping event: {}", line, error),
+            Error::Spec(spec) => write!(f, "{}", spec),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(err: redis::RedisError) -> Self {
+        Error::Redis(err)
+    }
+}
+
+enum Source {
+    Lines(Box<dyn BufRead + Send>),
+    Redis(redis::Connection),
+    RedisStream {
+        connection: redis::Connection,
+        key: String,
+        last_id: String,
+        pending: VecDeque<String>,
+    },
+}
+
+impl Source {
+    /// The next raw event, `None` at the end of files and closed sockets. Counts lines and messages in `position`
+    fn next(&mut self, position: &mut usize) -> Result<Option<String>, Error> {
+        match self {
+            Source::Lines(reader) => loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                *position += 1;
+                if !line.trim().is_empty() {
+                    return Ok(Some(line));
+                }
+            },
+            Source::Redis(connection) => loop {
+                if let Some(message) = redis::Msg::from_value(&connection.recv_response()?) {
+                    *position += 1;
+                    return Ok(Some(message.get_payload()?));
+                }
+            },
+            Source::RedisStream {
+                connection,
+                key,
+                last_id,
+                pending,
+            } => loop {
+                if let Some(event) = pending.pop_front() {
+                    *position += 1;
+                    return Ok(Some(event));
+                }
+                let reply: StreamReply = redis::cmd("XREAD")
+                    .arg("COUNT")
+                    .arg(STREAM_BATCH_SIZE)
+                    .arg("BLOCK")
+                    .arg(0)
+                    .arg("STREAMS")
+                    .arg(&*key)
+                    .arg(&*last_id)
+                    .query(connection)?;
+                for (id, fields) in reply.into_iter().flatten().flat_map(|(_, entries)| entries) {
+                    pending.extend(
+                        fields
+                            .into_iter()
+                            .filter(|(field, _)| field == "event")
+                            .map(|(_, event)| event),
+                    );
+                    *last_id = id;
+                }
+            },
+        }
+    }
+}
+
+/// Reads events from one of the sinks flagged publishes to. Blocks until the next event arrives,
+/// so async code should iterate on a blocking thread.
+///
+/// Events that don't parse are reported as [`Error::Decode`] and skipped, other errors end the subscriber.
+pub struct Subscriber {
+    source: Source,
+    position: usize,
+    failed: bool,
+}
+
+impl Subscriber {
+    /// Opens a source described by
+    /// - `redis://host[:port][/db]` for pub/sub, which only sees events published from now on,
+    /// - `redis-stream://host[:port][/db][?key=events&from=0]`, starting from the oldest event the stream
+    ///   still has (`from=$` for new events only, or any entry id), see [`StreamSpec`],
+    /// - `jsonl:PATH` (`-` for stdin) or `unix:PATH`, the same specs `flagged --events` takes.
+    pub fn open(spec: &str) -> Result<Subscriber, Error> {
+        if let Some(path) = spec.strip_prefix("jsonl:") {
+            Self::read_jsonl(path)
+        } else if let Some(path) = spec.strip_prefix("unix:") {
+            Ok(Self::from_reader(BufReader::new(UnixStream::connect(
+                path,
+            )?)))
+        } else if spec.starts_with("redis-stream://") {
+            let stream: StreamSpec = spec.parse().map_err(Error::Spec)?;
+            let client = redis::Client::open(stream.url)?;
+            Ok(Self::new(Source::RedisStream {
+                connection: client.get_connection()?,
+                key: stream.key,
+                last_id: stream.from,
+                pending: VecDeque::new(),
+            }))
+        } else if spec.starts_with("redis://") || spec.starts_with("rediss://") {
+            let mut connection = redis::Client::open(spec)?.get_connection()?;
+            redis::cmd("SUBSCRIBE")
+                .arg(CHANNEL)
+                .query::<()>(&mut connection)?;
+            Ok(Self::new(Source::Redis(connection)))
+        } else {
+            Err(Error::Spec(format!(
+                "unknown event source {:?}, expected redis://, redis-stream://, jsonl:PATH or unix:PATH",
+                spec
+            )))
+        }
+    }
+
+    /// Reads a JSONL recording, e.g. `statserver/events.jsonl`. `-` reads stdin
+    pub fn read_jsonl(path: impl AsRef<Path>) -> Result<Subscriber, Error> {
+        let path = path.as_ref();
+        if path == Path::new("-") {
+            return Ok(Self::from_reader(BufReader::new(io::stdin())));
+        }
+        let file = std::fs::File::open(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        Ok(Self::from_reader(BufReader::new(file)))
+    }
+
+    /// Reads JSON lines from any reader
+    pub fn from_reader(reader: impl BufRead + Send + 'static) -> Subscriber {
+        Self::new(Source::Lines(Box::new(reader)))
+    }
+
+    fn new(source: Source) -> Subscriber {
+        Subscriber {
+            source,
+            position: 0,
+            failed: false,
+        }
+    }
+}
+
+impl Iterator for Subscriber {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.source.next(&mut self.position) {
+            Ok(Some(event)) => Some(serde_json::from_str(&event).map_err(|error| Error::Decode {
+                line: self.position,
+                error,
+            })),
+            Ok(None) => None,
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventPayload;
+    use std::io::Write;
+
+    #[test]
+    fn reads_jsonl_recordings() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let session = "\"session_id\":\"6b7e3fb1-1c6a-4b0e-9a6e-2f1e4b1c9d3a\",\"timestamp\":\"2022-05-28T12:00:00Z\"";
+        writeln!(
+            file,
+            "{{{},\"payload\":{{\"t\":\"IntervalStart\"}}}}",
+            session
+        )
+        .unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "{{\"payload\":42}}").unwrap();
+        writeln!(
+            file,
+            "{{\"version\":2,{},\"payload\":{{\"t\":\"SessionEnd\",\"c\":{{\"intervals\":1}}}}}}",
+            session
+        )
+        .unwrap();
+
+        let spec = format!("jsonl:{}", file.path().display());
+        let events = Subscriber::open(&spec).unwrap().collect::<Vec<_>>();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            &events[0],
+            Ok(Event {
+                version: 1,
+                payload: EventPayload::IntervalStart(None),
+                ..
+            })
+        ));
+        assert!(matches!(&events[1], Err(Error::Decode { line: 3, .. })));
+        assert!(matches!(
+            &events[2],
+            Ok(Event {
+                payload: EventPayload::SessionEnd { intervals: 1 },
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            Subscriber::open("jsonl:/nonexistent/events.jsonl"),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(
+            Subscriber::open("redis-stream://localhost?since=10"),
+            Err(Error::Spec(_))
+        ));
+        assert!(matches!(Subscriber::open("stdout"), Err(Error::Spec(_))));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::ctfapi::VerdictClass;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+    Bold,
+}
+
+impl Color {
+    /// How verdicts of this class are shown
+    pub fn of_verdict(class: VerdictClass) -> Self {
+        match class {
+            VerdictClass::Accepted => Color::Green,
+            VerdictClass::Unknown => Color::Yellow,
+            _ => Color::Red,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Cyan => "36",
+            Color::Bold => "1",
+        }
+    }
+}
+
+/// Colors are only used if stdout is a terminal and `NO_COLOR` isn't set.
+fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED
+        .get_or_init(|| std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+}
+
+pub fn paint(text: impl Display, color: Color) -> String {
+    if enabled() {
+        format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+    } else {
+        text.to_string()
+    }
+}
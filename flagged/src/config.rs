@@ -2,32 +2,528 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::time::Duration;
 
+use crate::configfile;
 use crate::ctfapi::CTFApi;
+use crate::flagformat::FlagFormat;
+use crate::scanner::{Encoding, FlagMatcher, DEFAULT_MAX_LINE_LENGTH};
+use crate::submitter::SubmissionPolicy;
+use crate::targetfilter::{self, Filter};
+use crate::targetgen::TargetGenerator;
+use crate::template::Template;
+use crate::ticks::TickConfig;
 use std::collections::HashMap;
-use tokio::process::Command;
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub command: Vec<String>,
+    /// Field(s) identifying a target in events and output, e.g. "IP" or ["TEAM", "SERVICE"]
+    #[serde(default)]
+    pub primary_key: PrimaryKey,
     pub interval: f64,
     pub timeout: f64,
     pub concurrency: u64,
+    #[serde(default)]
     pub targets: Vec<HashMap<String, Value>>,
+    /// Generated targets are appended to `targets` once the config is loaded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generate: Vec<TargetGenerator>,
+    /// Only attack targets matching all of these, e.g. `IP in 10.32.0.0/16`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<Filter>,
+    /// Never attack targets matching any of these, e.g. `NAME == "NOP"`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<Filter>,
+    #[serde(default)]
+    pub flag_format: FlagFormat,
+    /// Also look for base64/hex encoded flags in exploit output
+    #[serde(default)]
+    pub decode: Vec<Encoding>,
+    /// Longer lines are split before being printed or reported
+    #[serde(default = "default_max_line_length")]
+    pub max_line_length: usize,
+    /// Flags older than this many rounds (or intervals, if not tick-aligned) are not submitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flag_lifetime: Option<u64>,
+    /// Align intervals to the game's ticks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick: Option<TickConfig>,
+    /// Submission backend, see --ctf-api
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctf_api: Option<String>,
+    /// Overrides the backend's batching and pacing, e.g. `{"batch_size": 100, "rate_limit": 20}`
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub submission: serde_json::Map<String, Value>,
+    /// Submission server address, see --submit-addr
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submit_addr: Option<String>,
+    /// Team token, see --token. Never published
+    #[serde(default, skip_serializing)]
+    pub token: Option<String>,
+    /// Name of the applied profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PrimaryKey {
+    Field(String),
+    Composite(Vec<String>),
+}
+
+impl Default for PrimaryKey {
+    fn default() -> Self {
+        PrimaryKey::Field("IP".into())
+    }
+}
+
+impl PrimaryKey {
+    /// Composite keys join their fields with a slash, e.g. "team1/ssh".
+    pub fn key_of(&self, env: &HashMap<String, String>) -> Result<String, String> {
+        let fields = match self {
+            PrimaryKey::Field(field) => std::slice::from_ref(field),
+            PrimaryKey::Composite(fields) => fields.as_slice(),
+        };
+        let values = fields
+            .iter()
+            .map(|field| {
+                env.get(field)
+                    .map(String::as_str)
+                    .ok_or_else(|| format!("missing primary key field {:?}", field))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(values.join("/"))
+    }
+}
+
+impl fmt::Display for PrimaryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrimaryKey::Field(field) => write!(f, "{}", field),
+            PrimaryKey::Composite(fields) => write!(f, "{}", fields.join("/")),
+        }
+    }
+}
+
+fn default_max_line_length() -> usize {
+    DEFAULT_MAX_LINE_LENGTH
+}
+
+pub fn value_to_string(value: &Value) -> String {
+    value
+        .as_str()
+        .map(|s| s.to_owned())
+        .unwrap_or_else(|| serde_json::to_string(value).unwrap())
+}
+
+/// A single problem with a config, e.g. `concurrency: must be at least 1`.
+#[derive(Debug)]
+pub struct Problem {
+    pub field: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Problem {
+        Problem {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.field, self.message)
+        }
+    }
+}
+
+/// Every problem found in a config file, printed as one `file: field: message` line each.
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", self.path.display(), problem)?;
+        }
+        Ok(())
+    }
+}
+
+// main() prints returned errors with Debug
+impl fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config\n{}", self)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Checks whether `program` would be found and is executable when run from `cwd`.
+fn check_executable(program: &str, cwd: &Path) -> Result<(), String> {
+    let candidates = if program.contains('/') {
+        vec![cwd.join(program)]
+    } else {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        std::env::split_paths(&path)
+            .map(|dir| dir.join(program))
+            .collect()
+    };
+    let mut found = false;
+    for candidate in candidates {
+        if let Ok(metadata) = std::fs::metadata(&candidate) {
+            found = true;
+            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+                return Ok(());
+            }
+        }
+    }
+    Err(match (found, program.contains('/')) {
+        (true, _) => format!("{:?} is not executable", program),
+        (false, true) => format!("{:?} does not exist", program),
+        (false, false) => format!("{:?} not found in PATH", program),
+    })
 }
 
 impl Config {
+    /// Reads the config file (JSON, TOML or YAML), applies the profile and `FLAGGED_*`
+    /// environment variables, then expands generated targets.
+    pub fn load(path: &Path, profile: Option<&str>) -> Result<Config, ConfigError> {
+        let error = |problem| ConfigError {
+            path: path.to_path_buf(),
+            problems: vec![problem],
+        };
+        let mut value = configfile::load(path)?;
+        configfile::apply_profile(&mut value, profile)
+            .map_err(|err| error(Problem::new("profiles", err)))?;
+        // other tools may use the prefix too, so typos only get a warning
+        for name in configfile::apply_env(&mut value, std::env::vars()) {
+            tracing::warn!("ignoring {}, it doesn't name a setting", name);
+        }
+        let mut config: Config = serde_json::from_value(value)
+            .map_err(|err| error(Problem::new("", err.to_string())))?;
+        config.profile = profile.map(str::to_string);
+        config
+            .expand_targets()
+            .map_err(|(idx, err)| error(Problem::new(format!("generate[{}]", idx), err)))?;
+        Ok(config)
+    }
+
+    /// How long flags stay valid, derived from `flag_lifetime` and the round length
+    pub fn flag_lifetime(&self) -> Option<Duration> {
+        let round_length = match &self.tick {
+            Some(tick) => tick.round_length,
+            None => self.interval,
+        };
+        self.flag_lifetime
+            .map(|rounds| Duration::from_secs_f64(rounds as f64 * round_length))
+    }
+
+    /// Combines the backend's flag format with the `flag_format` overrides
+    pub fn flag_matcher(&self, ctf_api: &CTFApi) -> Result<FlagMatcher, Problem> {
+        let (regexes, transforms) = self
+            .flag_format
+            .resolve(ctf_api)
+            .map_err(|err| Problem::new("flag_format", err.to_string()))?;
+        FlagMatcher::new(&regexes, transforms, &self.decode).map_err(|err| {
+            Problem::new(
+                "flag_format",
+                format!("flag regexes can't be combined: {}", err),
+            )
+        })
+    }
+
+    /// The backend's submission policy with the `submission` overrides applied
+    pub fn submission_policy(&self, ctf_api: &CTFApi) -> Result<SubmissionPolicy, String> {
+        self.merge_submission(ctf_api.submitter.policy())
+    }
+
+    fn merge_submission(&self, defaults: SubmissionPolicy) -> Result<SubmissionPolicy, String> {
+        let mut policy = serde_json::to_value(defaults).unwrap();
+        configfile::merge(&mut policy, Value::Object(self.submission.clone()));
+        let policy: SubmissionPolicy =
+            serde_json::from_value(policy).map_err(|err| err.to_string())?;
+        policy.check()?;
+        Ok(policy)
+    }
+
+    /// Validates the settings and builds the selected targets, reporting every problem found.
+    pub fn check(&self, cwd: &str) -> Result<Vec<Arc<Target>>, Vec<Problem>> {
+        let mut problems = Vec::new();
+
+        if self.concurrency == 0 {
+            problems.push(Problem::new("concurrency", "must be at least 1"));
+        }
+        // also rejects NaN
+        let interval_valid = self.interval > 0.0;
+        let timeout_valid = self.timeout > 0.0;
+        if !interval_valid {
+            problems.push(Problem::new("interval", "must be positive"));
+        }
+        if !timeout_valid {
+            problems.push(Problem::new("timeout", "must be positive"));
+        }
+        if interval_valid && timeout_valid && self.timeout > self.interval {
+            problems.push(Problem::new(
+                "timeout",
+                format!(
+                    "{}s exceeds the interval of {}s",
+                    self.timeout, self.interval
+                ),
+            ));
+        }
+
+        if let Some(tick) = &self.tick {
+            let round_length_valid = tick.round_length > 0.0;
+            if !round_length_valid {
+                problems.push(Problem::new("tick.round_length", "must be positive"));
+            } else if timeout_valid && self.timeout > tick.round_length {
+                problems.push(Problem::new(
+                    "timeout",
+                    format!(
+                        "{}s exceeds the round length of {}s",
+                        self.timeout, tick.round_length
+                    ),
+                ));
+            }
+            let offset_valid = tick.offset >= 0.0 && tick.offset < tick.round_length;
+            if round_length_valid && !offset_valid {
+                problems.push(Problem::new(
+                    "tick.offset",
+                    "must be between 0 and the round length",
+                ));
+            }
+            if tick.start.is_none() && tick.round_info.is_none() {
+                problems.push(Problem::new(
+                    "tick",
+                    "needs a start time or a round_info endpoint",
+                ));
+            }
+        }
+
+        let flag_format = &self.flag_format;
+        for (field, regexes) in [
+            ("regex", &flag_format.regex),
+            ("extra_regex", &flag_format.extra_regex),
+        ]
+        .iter()
+        {
+            for (idx, re) in regexes.iter().enumerate() {
+                if let Err(err) = regex::bytes::Regex::new(re) {
+                    let field = format!("flag_format.{}[{}]", field, idx);
+                    problems.push(Problem::new(field, err.to_string()));
+                }
+            }
+        }
+
+        if self.flag_lifetime == Some(0) {
+            problems.push(Problem::new("flag_lifetime", "must be at least 1 round"));
+        }
+
+        // the backend isn't known yet, so this checks the overrides against the generic defaults
+        if let Err(err) = self.merge_submission(SubmissionPolicy::default()) {
+            problems.push(Problem::new("submission", err));
+        }
+
+        if self.command.is_empty() {
+            problems.push(Problem::new("command", "must not be empty"));
+        }
+        let mut command_template = Vec::new();
+        for (idx, arg) in self.command.iter().enumerate() {
+            match Template::parse(arg) {
+                Ok(template) => command_template.push(template),
+                Err(err) => problems.push(Problem::new(format!("command[{}]", idx), err)),
+            }
+        }
+        if command_template.len() != self.command.len() || self.command.is_empty() {
+            return Err(problems);
+        }
+
+        let mut targets: Vec<Arc<Target>> = Vec::new();
+        let mut keys = HashMap::new();
+        for (idx, data) in self.targets.iter().enumerate() {
+            if !targetfilter::is_selected(data, &self.include, &self.exclude) {
+                continue;
+            }
+            let field = format!("targets[{}]", idx);
+            match Target::new(&self.primary_key, &command_template, data, cwd.to_string()) {
+                Ok(target) => {
+                    if let Some(other) = keys.insert(target.key.clone(), idx) {
+                        problems.push(Problem::new(
+                            field,
+                            format!(
+                                "duplicate key {:?}, also used by targets[{}]",
+                                target.key, other
+                            ),
+                        ));
+                    }
+                    targets.push(Arc::new(target));
+                }
+                Err(err) => problems.push(Problem::new(field, err)),
+            }
+        }
+
+        let mut programs = targets
+            .iter()
+            .map(|target| target.args[0].as_str())
+            .collect::<Vec<_>>();
+        programs.sort_unstable();
+        programs.dedup();
+        for program in programs {
+            if let Err(err) = check_executable(program, Path::new(cwd)) {
+                problems.push(Problem::new("command[0]", err));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(targets)
+        } else {
+            Err(problems)
+        }
+    }
+
+    pub fn command_template(&self) -> Result<Vec<Template>, String> {
+        self.command
+            .iter()
+            .map(|arg| Template::parse(arg))
+            .collect()
+    }
+
+    /// Appends generated targets. Errors include the index of the failing generator.
+    pub fn expand_targets(&mut self) -> Result<(), (usize, String)> {
+        for (idx, generator) in self.generate.iter().enumerate() {
+            let targets = generator.expand().map_err(|err| (idx, err))?;
+            self.targets.extend(targets);
+        }
+        Ok(())
+    }
+
+    /// Targets passing the include and exclude filters
+    pub fn selected_targets(&self) -> impl Iterator<Item = &HashMap<String, Value>> {
+        self.targets
+            .iter()
+            .filter(move |target| targetfilter::is_selected(target, &self.include, &self.exclude))
+    }
+
     pub fn explain(&self, ctf_api: &CTFApi) {
         println!("Configuration:");
+        if let Some(profile) = &self.profile {
+            println!("| profile: {:?}", profile);
+        }
         println!("| ctf_api: {:?}", ctf_api.name);
-        println!("| flag_regex: {:?}", ctf_api.flag_regex);
+        match self.flag_format.resolve(ctf_api) {
+            Ok((regexes, transforms)) => {
+                for regex in regexes {
+                    println!("| flag_regex: {:?}", regex.as_str());
+                }
+                if !transforms.is_empty() {
+                    println!("| transforms: {:?}", transforms);
+                }
+            }
+            Err(err) => println!("| flag_regex: invalid! {}", err),
+        }
+        if !self.decode.is_empty() {
+            println!("| decode: {:?}", self.decode);
+        }
+        if let (Some(rounds), Some(lifetime)) = (self.flag_lifetime, self.flag_lifetime()) {
+            println!("| flag_lifetime: {} rounds ({:?})", rounds, lifetime);
+        }
+        match self.submission_policy(ctf_api) {
+            Ok(policy) => {
+                let rate = match (policy.rate_limit, policy.burst) {
+                    (Some(rate), Some(burst)) => {
+                        format!(", {} flags/s (bursts of {})", rate, burst)
+                    }
+                    (Some(rate), None) => format!(", {} flags/s", rate),
+                    _ => String::new(),
+                };
+                println!(
+                    "| submission: batches of {} every {:?}{}, backoff {:?} to {:?}",
+                    policy.batch_size,
+                    Duration::from_secs_f64(policy.flush_latency),
+                    rate,
+                    Duration::from_secs_f64(policy.backoff_initial),
+                    Duration::from_secs_f64(policy.backoff_max),
+                );
+            }
+            Err(err) => println!("| submission: invalid! {}", err),
+        }
+        println!("| primary_key: {}", self.primary_key);
         println!("| concurrency: {:?}", self.concurrency);
         println!("| interval: {:?}", Duration::from_secs_f64(self.interval));
         println!("| timeout: {:?}", Duration::from_secs_f64(self.timeout));
-        println!("| #targets: {:?}", self.targets.len());
-        let batches = f64::ceil(self.targets.len() as f64 / self.concurrency as f64);
+        if let Some(tick) = &self.tick {
+            println!(
+                "| tick: {:?} rounds, starting {:?} after each tick",
+                Duration::from_secs_f64(tick.round_length),
+                Duration::from_secs_f64(tick.offset)
+            );
+            if let Some(start) = tick.start {
+                println!("| ~> round {} started at {}", tick.first_round, start);
+            }
+            if let Some(info) = &tick.round_info {
+                println!("| ~> round info from {}", info.url);
+            }
+        }
+        for filter in &self.include {
+            println!("| include: {}", filter);
+        }
+        for filter in &self.exclude {
+            println!("| exclude: {}", filter);
+        }
+        let selected = self.selected_targets().count();
+        if selected == self.targets.len() {
+            println!("| #targets: {:?}", selected);
+        } else {
+            println!(
+                "| #targets: {:?} ({} filtered out)",
+                selected,
+                self.targets.len() - selected
+            );
+        }
+        let batches = f64::ceil(selected as f64 / self.concurrency as f64);
         let worst_case_interval = Duration::from_secs_f64(batches * self.timeout);
         println!("| ~> worst case interval length: {:?}", worst_case_interval);
-        // TODO: explain target command templating
+        println!("| command: {:?}", self.command);
+        let sample = self.selected_targets().next();
+        match (self.command_template(), sample) {
+            (Ok(template), Some(sample)) => {
+                match Target::new(&self.primary_key, &template, sample, String::new()) {
+                    Ok(target) => println!("| ~> e.g. {}: {:?}", target.key, target.args),
+                    Err(err) => println!("| ~> invalid! {}", err),
+                }
+            }
+            (Ok(_), None) => {}
+            (Err(err), _) => println!("| ~> invalid! {}", err),
+        }
+    }
+
+    pub fn dump_targets(&self) {
+        println!("Targets:");
+        for target in &self.targets {
+            let selected = targetfilter::is_selected(target, &self.include, &self.exclude);
+            let fields = target.iter().collect::<std::collections::BTreeMap<_, _>>();
+            println!(
+                "| {} {}",
+                if selected { "+" } else { "-" },
+                serde_json::to_string(&fields).unwrap()
+            );
+        }
     }
 }
 
@@ -41,41 +537,29 @@ pub struct Target {
 
 impl Target {
     pub fn new(
-        primary_key: &str,
-        command_template: &[String],
+        primary_key: &PrimaryKey,
+        command_template: &[Template],
         data: &HashMap<String, Value>,
         cwd: String,
-    ) -> Target {
+    ) -> Result<Target, String> {
         let mut env = HashMap::new();
-        let mut args = Vec::new();
-
         for (k, v) in data.iter() {
-            let v = v
-                .as_str()
-                .map(|s| s.to_owned())
-                .unwrap_or_else(|| serde_json::to_string(v).unwrap());
-            env.insert(k.clone(), v);
+            env.insert(k.clone(), value_to_string(v));
         }
 
-        for arg in command_template {
-            let mut arg = arg.to_string();
-            for (k, v) in env.iter() {
-                arg = arg.replace(&format!("${}", k), v);
-            }
-            args.push(arg);
-        }
+        let key = primary_key.key_of(&env)?;
 
-        let key = env
-            .get(primary_key)
-            .expect("key PRIMARY_KEY (usually 'IP') missing for target")
-            .clone();
+        let args = command_template
+            .iter()
+            .map(|arg| arg.render(data))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        Target {
+        Ok(Target {
             env,
             args,
             cwd,
             key,
-        }
+        })
     }
 
     pub fn prepare(&self) -> Command {
@@ -90,3 +574,140 @@ impl Target {
         cmd
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn problems(config: Value, cwd: &Path) -> Vec<String> {
+        let config: Config = serde_json::from_value(config).unwrap();
+        match config.check(cwd.to_str().unwrap()) {
+            Ok(_) => Vec::new(),
+            Err(problems) => problems.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn check_reports_every_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("exploit.sh");
+        std::fs::write(&script, "").unwrap();
+
+        let config = json!({
+            "command": ["./exploit.sh", "$IP"],
+            "interval": 10,
+            "timeout": 30,
+            "concurrency": 0,
+            "targets": [{ "IP": "a" }, { "NAME": "b" }, { "IP": "a" }],
+        });
+        assert_eq!(
+            problems(config.clone(), dir.path()),
+            [
+                "concurrency: must be at least 1",
+                "timeout: 30s exceeds the interval of 10s",
+                "targets[1]: missing primary key field \"IP\"",
+                "targets[2]: duplicate key \"a\", also used by targets[0]",
+                "command[0]: \"./exploit.sh\" is not executable",
+            ]
+        );
+
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut config = config;
+        config["concurrency"] = json!(2);
+        config["timeout"] = json!(5);
+        config["targets"] = json!([{ "IP": "a" }, { "IP": "b" }]);
+        assert!(problems(config.clone(), dir.path()).is_empty());
+
+        config["command"] = json!([]);
+        assert_eq!(problems(config, dir.path()), ["command: must not be empty"]);
+    }
+
+    fn minimal() -> Value {
+        json!({
+            "command": ["sh", "-c", "true"],
+            "interval": 10,
+            "timeout": 5,
+            "concurrency": 1,
+            "targets": [],
+        })
+    }
+
+    #[test]
+    fn flag_regexes_must_compile_and_combine() {
+        let mut config = minimal();
+        config["flag_format"] = json!({ "regex": ["(?P<id>A+)"], "extra_regex": ["B+", "C{("] });
+        let found = problems(config.clone(), Path::new("."));
+        assert_eq!(found.len(), 1);
+        assert!(found[0].starts_with("flag_format.extra_regex[1]: "));
+
+        // every regex compiles on its own, but they can't share a group name
+        config["flag_format"] = json!({ "regex": ["(?P<id>A+)"], "extra_regex": ["(?P<id>B+)"] });
+        assert!(problems(config.clone(), Path::new(".")).is_empty());
+        let ctf_api = crate::ctfapi::choose(Some("noop".into()), &Default::default()).unwrap();
+        let config: Config = serde_json::from_value(config).unwrap();
+        let problem = config.flag_matcher(&ctf_api).err().unwrap();
+        assert!(problem
+            .to_string()
+            .starts_with("flag_format: flag regexes can't be combined"));
+    }
+
+    #[test]
+    fn submission_overrides_are_checked() {
+        let mut config = minimal();
+        config["submission"] = json!({ "batch_size": 0 });
+        assert_eq!(
+            problems(config.clone(), Path::new(".")),
+            ["submission: batch_size must be at least 1"]
+        );
+        config["submission"] = json!({ "batch_size": 100, "rate_limit": 5 });
+        assert!(problems(config, Path::new(".")).is_empty());
+    }
+
+    #[test]
+    fn submission_overrides_backend_defaults() {
+        let mut config = minimal();
+        config["submission"] = json!({ "flush_latency": 2.5 });
+        let config: Config = serde_json::from_value(config).unwrap();
+        let backend = SubmissionPolicy {
+            batch_size: 100,
+            ..Default::default()
+        };
+        let policy = config.merge_submission(backend).unwrap();
+        assert_eq!((policy.batch_size, policy.flush_latency), (100, 2.5));
+    }
+
+    #[test]
+    fn composite_primary_key() {
+        let config: Config = serde_json::from_value(json!({
+            "command": ["sh", "-c", "true"],
+            "primary_key": ["TEAM", "SERVICE"],
+            "interval": 10,
+            "timeout": 5,
+            "concurrency": 1,
+            "targets": [
+                { "TEAM": 1, "SERVICE": "ssh" },
+                { "TEAM": 1, "SERVICE": "web" },
+                { "TEAM": 2 },
+            ],
+        }))
+        .unwrap();
+        let problems = config.check(".").unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].to_string(),
+            "targets[2]: missing primary key field \"SERVICE\""
+        );
+
+        let env = HashMap::from([("TEAM".to_string(), "1".to_string())]);
+        assert_eq!(
+            PrimaryKey::default().key_of(&env).unwrap_err(),
+            "missing primary key field \"IP\""
+        );
+        let env = HashMap::from([
+            ("TEAM".to_string(), "1".to_string()),
+            ("SERVICE".to_string(), "ssh".to_string()),
+        ]);
+        assert_eq!(config.primary_key.key_of(&env).unwrap(), "1/ssh");
+    }
+}
@@ -0,0 +1,283 @@
+//! Reads JSON, TOML or YAML config files and resolves `extends`.
+//!
+//! `"extends": ["../game.toml", "../targets.yaml"]` loads the listed files (relative to the
+//! extending file) in order. Later files override earlier ones and the extending file overrides
+//! them all. Objects are merged key by key, everything else (including lists) is replaced.
+//!
+//! Named profiles (`"profiles": {"local": {...}}`) and `FLAGGED_*` environment variables are
+//! merged on top, in that order.
+
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::config::{ConfigError, Problem};
+
+const ENV_PREFIX: &str = "FLAGGED_";
+/// Variables that select what to load or how to log instead of overriding a setting
+const ENV_IGNORED: &[&str] = &["PROFILE", "LOG"];
+/// Settings `FLAGGED_*` variables can override
+const ENV_SETTINGS: &[&str] = &[
+    "command",
+    "primary_key",
+    "interval",
+    "timeout",
+    "concurrency",
+    "targets",
+    "generate",
+    "include",
+    "exclude",
+    "flag_format",
+    "decode",
+    "max_line_length",
+    "flag_lifetime",
+    "tick",
+    "ctf_api",
+    "submission",
+    "submit_addr",
+    "token",
+];
+/// String settings, taken verbatim even if they look like JSON (e.g. a numeric token)
+const ENV_STRINGS: &[&str] = &["ctf_api", "submit_addr", "token"];
+
+/// Tried in order if the default config file doesn't exist
+pub const DEFAULT_NAMES: &[&str] = &["attacc.json", "attacc.toml", "attacc.yaml", "attacc.yml"];
+
+fn error(path: &Path, field: &str, message: impl ToString) -> ConfigError {
+    ConfigError {
+        path: path.to_path_buf(),
+        problems: vec![Problem {
+            field: field.to_string(),
+            message: message.to_string(),
+        }],
+    }
+}
+
+fn parse(path: &Path) -> Result<Value, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|err| error(path, "", err))?;
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    match extension {
+        "toml" => toml::from_str::<toml::Value>(&content)
+            .map_err(|err| error(path, "", err))
+            .and_then(|value| serde_json::to_value(value).map_err(|err| error(path, "", err))),
+        "yaml" | "yml" => serde_yaml::from_str(&content).map_err(|err| error(path, "", err)),
+        _ => serde_json::from_str(&content).map_err(|err| error(path, "", err)),
+    }
+}
+
+pub(crate) fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+fn load_extending(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Value, ConfigError> {
+    let canonical = std::fs::canonicalize(path).map_err(|err| error(path, "", err))?;
+    if chain.contains(&canonical) {
+        return Err(error(path, "extends", "cyclic extends"));
+    }
+
+    let mut value = parse(path)?;
+    let extends = match value.as_object_mut().map(|object| object.remove("extends")) {
+        None => return Err(error(path, "", "expected a table/object at the top level")),
+        Some(None) => return Ok(value),
+        Some(Some(Value::String(file))) => vec![file],
+        Some(Some(Value::Array(files))) => files
+            .into_iter()
+            .map(|file| match file {
+                Value::String(file) => Ok(file),
+                other => Err(error(
+                    path,
+                    "extends",
+                    format!("expected a path, got {}", other),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Some(Some(other)) => {
+            return Err(error(
+                path,
+                "extends",
+                format!("expected a path or list of paths, got {}", other),
+            ))
+        }
+    };
+
+    chain.push(canonical);
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut merged = Value::Object(Default::default());
+    for file in extends {
+        merge(&mut merged, load_extending(&dir.join(file), chain)?);
+    }
+    chain.pop();
+
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+/// Merges the selected profile over the config. The `profiles` table itself is dropped.
+pub fn apply_profile(value: &mut Value, profile: Option<&str>) -> Result<(), String> {
+    let mut profiles = match value
+        .as_object_mut()
+        .and_then(|object| object.remove("profiles"))
+    {
+        Some(Value::Object(profiles)) => profiles,
+        Some(_) => return Err("expected a table/object of profiles".into()),
+        None => Default::default(),
+    };
+    if let Some(name) = profile {
+        let overrides = profiles.remove(name).ok_or_else(|| {
+            let known = profiles.keys().cloned().collect::<Vec<_>>();
+            format!("unknown profile {:?}, expected one of {:?}", name, known)
+        })?;
+        merge(value, overrides);
+    }
+    Ok(())
+}
+
+/// Overrides settings from environment variables: `FLAGGED_INTERVAL=30` sets `interval` and
+/// `FLAGGED_FLAG_FORMAT__REGEX='["..."]'` sets `flag_format.regex`. Values of string settings
+/// are used as they are, others are parsed as JSON if possible and used as strings otherwise.
+/// Returns the variables that don't name a setting, which are ignored.
+pub fn apply_env(value: &mut Value, vars: impl Iterator<Item = (String, String)>) -> Vec<String> {
+    let mut unknown = Vec::new();
+    for (name, raw) in vars {
+        let path = match name.strip_prefix(ENV_PREFIX) {
+            Some(path) if !ENV_IGNORED.contains(&path) => path.to_lowercase(),
+            _ => continue,
+        };
+        let setting = path.split("__").next().unwrap_or_default();
+        if !ENV_SETTINGS.contains(&setting) {
+            unknown.push(name);
+            continue;
+        }
+        let parsed = if ENV_STRINGS.contains(&path.as_str()) {
+            Value::String(raw)
+        } else {
+            serde_json::from_str(&raw).unwrap_or(Value::String(raw))
+        };
+        let mut overrides = parsed;
+        for key in path.rsplit("__") {
+            let mut object = serde_json::Map::new();
+            object.insert(key.to_string(), overrides);
+            overrides = Value::Object(object);
+        }
+        merge(value, overrides);
+    }
+    unknown
+}
+
+/// Reads a config file and everything it extends into a single JSON value.
+pub fn load(path: &Path) -> Result<Value, ConfigError> {
+    load_extending(path, &mut Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn formats_and_extends() {
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join("game");
+        std::fs::create_dir(&game).unwrap();
+        std::fs::write(
+            game.join("defaults.toml"),
+            "# shared settings\ninterval = 120\ntimeout = 30\n\n[flag_format]\nregex = ['FLAG\\{\\w+\\}']\n",
+        )
+        .unwrap();
+        std::fs::write(
+            game.join("targets.yaml"),
+            "# all teams\ntargets:\n  - IP: 10.0.0.1\n  - IP: 10.0.0.2\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("attacc.json"),
+            r#"{
+                "extends": ["game/defaults.toml", "game/targets.yaml"],
+                "command": ["./exploit.py"],
+                "timeout": 10,
+                "flag_format": { "transforms": ["trim"] }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            load(&dir.path().join("attacc.json")).unwrap(),
+            json!({
+                "interval": 120,
+                "timeout": 10,
+                "command": ["./exploit.py"],
+                "flag_format": { "regex": ["FLAG\\{\\w+\\}"], "transforms": ["trim"] },
+                "targets": [{ "IP": "10.0.0.1" }, { "IP": "10.0.0.2" }],
+            })
+        );
+    }
+
+    #[test]
+    fn profiles_and_env() {
+        let mut value = json!({
+            "interval": 120,
+            "submit_addr": "10.13.37.1:1337",
+            "flag_format": { "regex": ["A"], "transforms": ["trim"] },
+            "profiles": {
+                "local": { "submit_addr": "127.0.0.1:31337", "targets": [{ "IP": "127.0.0.1" }] },
+            },
+        });
+        apply_profile(&mut value, Some("local")).unwrap();
+        let vars = [
+            ("FLAGGED_INTERVAL", "30"),
+            ("FLAGGED_CTF_API", "saarctf"),
+            ("FLAGGED_TOKEN", "123456"),
+            ("FLAGGED_LOG", "debug"),
+            ("FLAGGED_FLAG_FORMAT__REGEX", r#"["B"]"#),
+            ("FLAGGED_PROFILE", "local"),
+            ("PATH", "/usr/bin"),
+        ];
+        let unknown = apply_env(
+            &mut value,
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        assert!(unknown.is_empty());
+        assert_eq!(
+            value,
+            json!({
+                "interval": 30,
+                "ctf_api": "saarctf",
+                "token": "123456",
+                "submit_addr": "127.0.0.1:31337",
+                "targets": [{ "IP": "127.0.0.1" }],
+                "flag_format": { "regex": ["B"], "transforms": ["trim"] },
+            })
+        );
+
+        let vars = [("FLAGGED_INTERVALL", "30"), ("FLAGGED_", "")];
+        let unknown = apply_env(
+            &mut value,
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        assert_eq!(unknown, ["FLAGGED_INTERVALL", "FLAGGED_"]);
+        assert_eq!(value["interval"], 30);
+
+        let mut value = json!({ "profiles": { "game": {} } });
+        assert!(apply_profile(&mut value, Some("local")).is_err());
+    }
+
+    #[test]
+    fn cyclic_extends() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "extends: b.yml\n").unwrap();
+        std::fs::write(dir.path().join("b.yml"), "extends: [a.yaml]\n").unwrap();
+        let err = load(&dir.path().join("a.yaml")).unwrap_err();
+        assert_eq!(err.problems[0].message, "cyclic extends");
+    }
+}
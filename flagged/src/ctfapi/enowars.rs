@@ -1,29 +1,21 @@
-use crate::ctfapi::{CTFApi, Flag, Submitter};
+use crate::ctfapi::{read_verdict, resolve, CTFApi, Flag, Settings, Submitter};
+use crate::flagformat::Transform;
 use regex::bytes::Regex;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::io::{BufReader, Write};
+use std::net::TcpStream;
 use std::time::Duration;
-use unicode_segmentation::UnicodeSegmentation;
 
-pub struct EnowarsSubmitter;
+pub struct EnowarsSubmitter {
+    addr: String,
+}
 
 impl Submitter for EnowarsSubmitter {
     fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
-        let addr = "10.0.13.37:1337"
-            .to_socket_addrs()
-            .expect("failed to resolve submission server")
-            .next()
-            .expect("failed to resovle submission server");
+        let addr = resolve(&self.addr)?;
         let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(1))?;
         let mut data = Vec::new();
         for flag in batch {
-            let flag_str = flag.to_string();
-            let g = UnicodeSegmentation::graphemes(&*flag_str, true).collect::<Vec<&str>>();
-            let mut flag_final = String::new();
-            for grapheme in g.iter().take(5) {
-                flag_final += grapheme;
-            }
-            data.extend_from_slice(flag_final.as_bytes());
+            data.extend_from_slice(flag.as_bytes());
             data.push(b'\n');
         }
         stream.write_all(&data)?;
@@ -31,25 +23,29 @@ impl Submitter for EnowarsSubmitter {
         let mut reader = BufReader::new(stream);
 
         for flag in batch {
-            let mut status = String::new();
-            let size = reader.read_line(&mut status)?;
-            status.truncate(size);
-            if status.ends_with('\n') {
-                status.truncate(size - 1);
-            }
-            flag.set_verdict(status);
+            flag.set_verdict(read_verdict(&mut reader)?);
         }
         Ok(())
     }
+
+    fn check(&self) -> std::io::Result<()> {
+        resolve(&self.addr).map(|_| ())
+    }
 }
 
-pub fn ctfapi() -> CTFApi {
+pub fn ctfapi(settings: &Settings) -> CTFApi {
     let flag_regex = Regex::new(r"🏳️‍🌈[^\n]*").unwrap();
 
     CTFApi {
-        name: "faust".into(),
-        flag_regex,
-        submitter: Box::new(EnowarsSubmitter),
+        name: "enowars".into(),
+        flag_regex: vec![flag_regex],
+        transforms: vec![Transform::TruncateGraphemes(5)],
+        submitter: Box::new(EnowarsSubmitter {
+            addr: settings
+                .addr
+                .clone()
+                .unwrap_or_else(|| "10.0.13.37:1337".into()),
+        }),
         test_flag: Some("🏳️‍🌈F̦̃́L̀̀̀À̀̀G̀̀̀".into()),
     }
 }
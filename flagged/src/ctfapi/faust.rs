@@ -1,18 +1,16 @@
-use crate::ctfapi::{CTFApi, Flag, Submitter};
+use crate::ctfapi::{read_verdict, resolve, CTFApi, Flag, Settings, Submitter};
 use regex::bytes::Regex;
 use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::net::TcpStream;
 use std::time::Duration;
 
-pub struct FaustSubmitter;
+pub struct FaustSubmitter {
+    addr: String,
+}
 
 impl Submitter for FaustSubmitter {
     fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
-        let addr = "submission.faustctf.net:666"
-            .to_socket_addrs()
-            .expect("failed to resolve submission server")
-            .next()
-            .expect("failed to resovle submission server");
+        let addr = resolve(&self.addr)?;
         let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(1))?;
         let mut data = Vec::new();
         for flag in batch {
@@ -33,25 +31,29 @@ impl Submitter for FaustSubmitter {
         assert_eq!(welcome, "One flag per line please!\n");
 
         for flag in batch {
-            let mut status = String::new();
-            let size = reader.read_line(&mut status)?;
-            status.truncate(size);
-            if status.ends_with('\n') {
-                status.truncate(size - 1);
-            }
-            flag.set_verdict(status);
+            flag.set_verdict(read_verdict(&mut reader)?);
         }
         Ok(())
     }
+
+    fn check(&self) -> std::io::Result<()> {
+        resolve(&self.addr).map(|_| ())
+    }
 }
 
-pub fn ctfapi() -> CTFApi {
+pub fn ctfapi(settings: &Settings) -> CTFApi {
     let flag_regex = Regex::new(r"FAUST_[A-Za-z0-9/\\\\+]{32}").unwrap();
 
     CTFApi {
         name: "faust".into(),
-        flag_regex,
-        submitter: Box::new(FaustSubmitter),
+        flag_regex: vec![flag_regex],
+        transforms: Vec::new(),
+        submitter: Box::new(FaustSubmitter {
+            addr: settings
+                .addr
+                .clone()
+                .unwrap_or_else(|| "submission.faustctf.net:666".into()),
+        }),
         test_flag: Some("FAUST_TESTTESTTESTTESTTESTTESTTESTTEST".into()),
     }
 }
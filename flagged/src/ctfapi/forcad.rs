@@ -1,10 +1,10 @@
 //! ForcAD submitter:
 //! https://github.com/pomo-mondreganto/ForcAD/blob/master/backend/flag_submitter/tcp_server/server.py
 
-use crate::ctfapi::{CTFApi, Flag, Submitter};
+use crate::ctfapi::{read_verdict, resolve, CTFApi, Flag, Settings, Submitter};
 use regex::bytes::Regex;
 use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::net::TcpStream;
 use std::time::Duration;
 
 pub struct ForcadSubmitter {
@@ -14,12 +14,7 @@ pub struct ForcadSubmitter {
 
 impl Submitter for ForcadSubmitter {
     fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
-        let addr = self
-            .addr
-            .to_socket_addrs()
-            .expect("failed to resolve submission server")
-            .next()
-            .expect("failed to resovle submission server");
+        let addr = resolve(&self.addr)?;
         let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(1))?;
         stream.write_all(self.team_token.as_bytes())?;
         stream.write_all(b"\n")?;
@@ -44,26 +39,28 @@ impl Submitter for ForcadSubmitter {
         assert_eq!(welcome, "Now enter your flags, one in a line:\n");
 
         for flag in batch {
-            let mut status = String::new();
-            let size = reader.read_line(&mut status)?;
-            status.truncate(size);
-            if status.ends_with('\n') {
-                status.truncate(size - 1);
-            }
-            flag.set_verdict(status);
+            flag.set_verdict(read_verdict(&mut reader)?);
         }
         Ok(())
     }
+
+    fn check(&self) -> std::io::Result<()> {
+        resolve(&self.addr).map(|_| ())
+    }
 }
 
-pub fn ctfapi() -> CTFApi {
+pub fn ctfapi(settings: &Settings) -> CTFApi {
     CTFApi {
         name: String::from("forcad"),
         test_flag: Some("TESTTESTTESTTESTTESTTESTTESTTES=".into()),
-        flag_regex: Regex::new(r"\w{31}=").unwrap(),
+        flag_regex: vec![Regex::new(r"\w{31}=").unwrap()],
+        transforms: Vec::new(),
         submitter: Box::new(ForcadSubmitter {
-            addr: "10.10.10.10:31337".into(),
-            team_token: "FIXME".into(), // TODO: --team-token or something? could also read from environment?
+            addr: settings
+                .addr
+                .clone()
+                .unwrap_or_else(|| "10.10.10.10:31337".into()),
+            team_token: settings.token.clone().unwrap_or_else(|| "FIXME".into()),
         }),
     }
 }
@@ -1,5 +1,7 @@
+use crate::flagformat::Transform;
+use crate::submitter::SubmissionPolicy;
+use chrono::{DateTime, Utc};
 use regex::bytes::Regex;
-use std::sync::atomic;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -21,30 +23,143 @@ mod enowars;
 #[cfg(feature = "ctfapi-mhackectf")]
 mod mhackectf;
 
+/// Rough classification of the free-form verdict strings returned by gameservers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VerdictClass {
+    Accepted,
+    Duplicate,
+    Expired,
+    Own,
+    Nop,
+    Invalid,
+    Unknown,
+}
+
+impl VerdictClass {
+    pub const ALL: [VerdictClass; 7] = [
+        VerdictClass::Accepted,
+        VerdictClass::Duplicate,
+        VerdictClass::Expired,
+        VerdictClass::Own,
+        VerdictClass::Nop,
+        VerdictClass::Invalid,
+        VerdictClass::Unknown,
+    ];
+
+    pub fn of(verdict: &str) -> Self {
+        let verdict = verdict.to_lowercase();
+        let has = |needles: &[&str]| needles.iter().any(|needle| verdict.contains(needle));
+        if has(&["already", "dup", "resubmit"]) {
+            VerdictClass::Duplicate
+        } else if has(&["expired", "too old", "old flag"]) {
+            VerdictClass::Expired
+        } else if has(&["own flag", "your own", "own team"]) {
+            VerdictClass::Own
+        } else if has(&["nop"]) {
+            VerdictClass::Nop
+        } else if has(&[
+            "invalid",
+            "[err]",
+            "error",
+            "denied",
+            "unknown flag",
+            "not a flag",
+        ]) {
+            VerdictClass::Invalid
+        } else if verdict.starts_with("ok") || has(&["[ok]", "accepted", "congrat"]) {
+            VerdictClass::Accepted
+        } else {
+            VerdictClass::Unknown
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VerdictClass::Accepted => "accepted",
+            VerdictClass::Duplicate => "duplicate",
+            VerdictClass::Expired => "expired",
+            VerdictClass::Own => "own",
+            VerdictClass::Nop => "nop",
+            VerdictClass::Invalid => "invalid",
+            VerdictClass::Unknown => "unknown",
+        }
+    }
+
+    /// Whether the gameserver definitely refused the flag
+    pub fn is_rejected(self) -> bool {
+        !matches!(self, VerdictClass::Accepted | VerdictClass::Unknown)
+    }
+}
+
 pub struct Flag {
     flag: String,
+    /// Key of the target this flag was captured from
+    target: String,
+    /// Interval of the session the flag was captured in
+    pub interval: u64,
+    /// Game round the flag was captured in, if intervals are tick-aligned
+    pub round: Option<u64>,
+    pub captured_at: DateTime<Utc>,
     run_handle: Arc<Mutex<crate::events::SessionRunHandle>>,
-    has_verdict: atomic::AtomicBool,
+    verdict: std::sync::Mutex<Option<String>>,
+    /// Given up without a verdict, see `give_up`
+    dropped: std::sync::atomic::AtomicBool,
 }
 
 impl Flag {
-    pub fn new(flag: &str, run_handle: &Arc<Mutex<crate::events::SessionRunHandle>>) -> Self {
+    pub fn new(
+        flag: &str,
+        target: &str,
+        run_handle: &Arc<Mutex<crate::events::SessionRunHandle>>,
+    ) -> Self {
         Flag {
             flag: flag.to_string(),
+            target: target.to_string(),
+            interval: 0,
+            round: None,
+            captured_at: Utc::now(),
             run_handle: run_handle.clone(),
-            has_verdict: false.into(),
+            verdict: std::sync::Mutex::new(None),
+            dropped: Default::default(),
         }
     }
 
+    pub fn captured_in(mut self, interval: u64, round: Option<u64>) -> Self {
+        self.interval = interval;
+        self.round = round;
+        self
+    }
+
+    pub fn age(&self) -> chrono::Duration {
+        Utc::now() - self.captured_at
+    }
+
+    pub fn verdict(&self) -> Option<String> {
+        self.verdict.lock().unwrap().clone()
+    }
+
+    pub fn has_verdict(&self) -> bool {
+        self.verdict.lock().unwrap().is_some()
+    }
+
     pub fn set_verdict(&self, verdict: String) {
-        let had_verdict = self.has_verdict.swap(true, atomic::Ordering::SeqCst);
-        if had_verdict {
-            eprintln!(
-                "[WARN] duplicate verdict set for flag {}! ctfapi broken?",
+        let had_verdict = self.verdict.lock().unwrap().replace(verdict.clone());
+        if had_verdict.is_some() {
+            tracing::warn!(
+                target = %self.target,
+                "duplicate verdict set for flag {}! ctfapi broken?",
                 self
             );
         }
-        println!("{} -> {}", self.flag, verdict);
+        crate::metrics::metrics().verdict(&self.target, &verdict);
+        tracing::info!(
+            target = %self.target,
+            interval = self.interval,
+            class = VerdictClass::of(&verdict).as_str(),
+            "{} -> {}",
+            self.flag,
+            verdict
+        );
         let run_handle = self.run_handle.clone();
         let flag = self.flag.clone();
         // FIXME: this is ugly and hides panics
@@ -52,6 +167,25 @@ impl Flag {
             run_handle.lock().await.flag_verdict(flag, verdict);
         });
     }
+
+    /// Gives up on the flag without a verdict from the gameserver, e.g. because it expired.
+    /// Published as `FlagDropped` and counted separately from verdicts.
+    pub fn give_up(&self, reason: &'static str) {
+        self.dropped
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        crate::metrics::metrics()
+            .flags_dropped
+            .inc(&[&self.target, reason]);
+        tracing::info!(target = %self.target, interval = self.interval, "{} dropped: {}", self.flag, reason);
+        let run_handle = self.run_handle.clone();
+        let flag = self.flag.clone();
+        tokio::spawn(async move {
+            run_handle
+                .lock()
+                .await
+                .flag_dropped(flag, reason.to_string());
+        });
+    }
 }
 
 impl std::ops::Deref for Flag {
@@ -75,25 +209,123 @@ impl std::fmt::Display for Flag {
 
 impl Drop for Flag {
     fn drop(&mut self) {
-        let verdict = self.has_verdict.load(atomic::Ordering::SeqCst);
-        if !verdict {
-            eprintln!(
-                "[WARN] flag {} dropped without setting verdict! ctfapi broken?",
+        if self.verdict.lock().unwrap().is_none()
+            && !self.dropped.load(std::sync::atomic::Ordering::Relaxed)
+        {
+            tracing::warn!(
+                target = %self.target,
+                "flag {} dropped without setting verdict! ctfapi broken?",
                 self
             );
         }
     }
 }
 
+/// Submits a batch of flags and sets their verdicts.
+///
+/// Errors may happen halfway through a batch: flags that already received a verdict are kept,
+/// only the rest is retried. Flags still without a verdict after `Ok(())` are reported as
+/// unanswered by the batcher.
 pub trait Submitter {
     fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()>;
+
+    /// Finds settings that can't work before anything is submitted, e.g. an address that doesn't resolve
+    fn check(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Batching and pacing the gameserver copes with, see the config's `submission` table
+    fn policy(&self) -> SubmissionPolicy {
+        SubmissionPolicy::default()
+    }
+}
+
+/// Resolves the address of a submission server, e.g. `host:port`.
+pub(crate) fn resolve(addr: &str) -> std::io::Result<std::net::SocketAddr> {
+    use std::net::ToSocketAddrs;
+    addr.to_socket_addrs()
+        .map_err(|err| {
+            std::io::Error::new(err.kind(), format!("failed to resolve {:?}: {}", addr, err))
+        })?
+        .next()
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{:?} doesn't resolve to any address", addr),
+            )
+        })
+}
+
+/// Reads a line-based verdict. A closed connection is an error rather than an empty verdict.
+pub(crate) fn read_verdict(reader: &mut impl std::io::BufRead) -> std::io::Result<String> {
+    let mut status = String::new();
+    if reader.read_line(&mut status)? == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "connection closed before all verdicts were received",
+        ));
+    }
+    if status.ends_with('\n') {
+        status.pop();
+    }
+    Ok(status)
 }
 
 struct NoopSubmitter;
 impl Submitter for NoopSubmitter {
     fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
         for flag in batch {
-            flag.set_verdict(format!("NoopSubmitter: {}", &flag[5..8]));
+            let tag = flag.get(5..8).unwrap_or(flag);
+            flag.set_verdict(format!("NoopSubmitter: {}", tag));
+        }
+        Ok(())
+    }
+}
+
+/// Connection details for the submission backend. Unset fields fall back to the backend's defaults.
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    /// Submission server address, or URL for HTTP-based backends
+    pub addr: Option<String>,
+    pub token: Option<String>,
+}
+
+/// Records flags instead of submitting them. Used by `--dry-run`.
+pub struct DryRunSubmitter {
+    out: std::sync::Mutex<std::fs::File>,
+}
+
+impl DryRunSubmitter {
+    pub fn create(path: &std::path::Path) -> std::io::Result<Self> {
+        let out = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(DryRunSubmitter {
+            out: std::sync::Mutex::new(out),
+        })
+    }
+}
+
+impl Submitter for DryRunSubmitter {
+    fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
+        let timestamp = Utc::now();
+        let mut data = Vec::new();
+        for flag in batch {
+            let record = serde_json::json!({
+                "flag": flag.flag,
+                "target": flag.target,
+                "interval": flag.interval,
+                "round": flag.round,
+                "captured_at": flag.captured_at,
+                "timestamp": timestamp,
+            });
+            serde_json::to_writer(&mut data, &record)?;
+            data.push(b'\n');
+        }
+        std::io::Write::write_all(&mut *self.out.lock().unwrap(), &data)?;
+        for flag in batch {
+            flag.set_verdict("DRY RUN".into());
         }
         Ok(())
     }
@@ -101,60 +333,92 @@ impl Submitter for NoopSubmitter {
 
 pub struct CTFApi {
     pub name: String,
-    pub flag_regex: Regex,
+    pub flag_regex: Vec<Regex>,
+    pub transforms: Vec<Transform>,
     pub test_flag: Option<String>,
     pub submitter: Box<dyn Submitter + Sync + Send>,
 }
 
-fn ctf_apis() -> Vec<CTFApi> {
+fn ctf_apis(settings: &Settings) -> Vec<CTFApi> {
     vec![
         CTFApi {
             name: String::from("noop"),
             test_flag: None,
-            flag_regex: Regex::new(r"FLAG\{[a-zA-Z0-9-_]{32}\}").unwrap(),
+            flag_regex: vec![Regex::new(r"FLAG\{[a-zA-Z0-9-_]{32}\}").unwrap()],
+            transforms: Vec::new(),
             submitter: Box::new(NoopSubmitter),
         },
         #[cfg(feature = "ctfapi-saarctf")]
-        saarctf::ctfapi(),
+        saarctf::ctfapi(settings),
         #[cfg(feature = "ctfapi-ructf")]
-        ructf::ctfapi(),
+        ructf::ctfapi(settings),
         #[cfg(feature = "ctfapi-forcad")]
-        forcad::ctfapi(),
+        forcad::ctfapi(settings),
         #[cfg(feature = "ctfapi-faust")]
-        faust::ctfapi(),
+        faust::ctfapi(settings),
         #[cfg(feature = "ctfapi-enowars")]
-        enowars::ctfapi(),
+        enowars::ctfapi(settings),
         #[cfg(feature = "ctfapi-mhackectf")]
-        mhackectf::ctfapi(),
+        mhackectf::ctfapi(settings),
     ]
 }
 
-pub fn choose(name: Option<String>) -> CTFApi {
-    let mut apis = ctf_apis();
+/// Picks the backend by name. Without a name, the only backend compiled in besides noop is used.
+pub fn choose(name: Option<String>, settings: &Settings) -> Result<CTFApi, String> {
+    let mut apis = ctf_apis(settings);
+    let names = || {
+        apis.iter()
+            .map(|api| api.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
 
-    if name == Some("help".into()) {
-        println!("Supported backends:");
-        for api in &apis {
-            println!("- {}", api.name);
-        }
-        panic!();
+    match name.as_deref() {
+        Some("help") => Err(format!("supported backends: {}", names())),
+        Some(name) => match apis.iter().position(|api| api.name == name) {
+            Some(idx) => Ok(apis.swap_remove(idx)),
+            None => Err(format!(
+                "unknown backend {:?}, supported: {}",
+                name,
+                names()
+            )),
+        },
+        None if apis.len() == 2 => Ok(apis.pop().unwrap()),
+        None => Err(format!(
+            "flagged was compiled with multiple backends, pick one of: {}",
+            names()
+        )),
     }
+}
 
-    if apis.len() == 2 {
-        let target = apis.pop().unwrap();
-        if let Some(name) = name {
-            assert_eq!(target.name, name, "unable to satisfy ctfapi choice");
-        }
-        target
-    } else {
-        let name = name.expect(
-            "flagged was compiled with multiple ctfapi backends. pass --ctf-api=help to list options",
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choose_lists_supported_backends() {
+        assert_eq!(
+            choose(Some("noop".into()), &Default::default())
+                .ok()
+                .map(|api| api.name),
+            Some("noop".to_string())
         );
-        for target in apis.into_iter() {
-            if target.name == name {
-                return target;
-            }
-        }
-        panic!("unable to satisfy ctfapi choice");
+        let unknown = choose(Some("nope".into()), &Default::default());
+        assert!(unknown
+            .err()
+            .unwrap()
+            .starts_with("unknown backend \"nope\", supported: noop"));
+    }
+
+    #[test]
+    fn resolve_reports_bad_addresses() {
+        assert_eq!(
+            resolve("127.0.0.1:31337").unwrap(),
+            "127.0.0.1:31337".parse().unwrap()
+        );
+        let err = resolve("127.0.0.1").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("failed to resolve \"127.0.0.1\": "));
     }
 }
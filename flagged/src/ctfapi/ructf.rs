@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 
-use crate::ctfapi::{CTFApi, Flag, Submitter};
+use crate::ctfapi::{CTFApi, Flag, Settings, Submitter};
+use crate::submitter::SubmissionPolicy;
 use regex::bytes::Regex;
 
 struct HttpSubmitter {
+    url: String,
     token: String,
 }
 
@@ -19,36 +21,64 @@ impl Submitter for HttpSubmitter {
         let client = reqwest::blocking::Client::new();
         let flags = batch.iter().map(|f| f.flag.clone()).collect::<Vec<_>>();
         let resp = client
-            .put("http://monitor.ructfe.org/flags")
+            .put(&self.url)
             .header("X-Team-Token", self.token.clone())
             .json(&flags)
             .send()
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "request failed"))?
+            .map_err(|_| std::io::Error::other("request failed"))?
             .json::<Vec<FlagResp>>()
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "invalid json"))?;
+            .map_err(|_| std::io::Error::other("invalid json"))?;
         let flag_results = resp
             .iter()
             .map(|res| (res.flag.clone(), res))
             .collect::<HashMap<_, _>>();
+        let mut missing = 0;
         for flag in batch {
-            if let Some(res) = flag_results.get(&flag.flag) {
-                let _ = res.status; // TODO: set verdict accepted
-                flag.set_verdict(res.msg.clone());
+            match flag_results.get(&flag.flag) {
+                Some(res) => {
+                    let _ = res.status; // TODO: set verdict accepted
+                    flag.set_verdict(res.msg.clone());
+                }
+                // left without a verdict, the batcher reports these as unanswered
+                None => missing += 1,
             }
         }
+        if missing > 0 {
+            tracing::warn!(
+                "ructf response is missing {} of {} flags",
+                missing,
+                batch.len()
+            );
+        }
         Ok(())
     }
+
+    fn policy(&self) -> SubmissionPolicy {
+        // the jury takes up to 100 flags per request
+        SubmissionPolicy {
+            batch_size: 100,
+            ..Default::default()
+        }
+    }
 }
 
-pub fn ctfapi() -> CTFApi {
+pub fn ctfapi(settings: &Settings) -> CTFApi {
     let flag_regex = Regex::new(r"\w{31}=").unwrap();
 
     CTFApi {
         name: "ructf".into(),
-        flag_regex,
+        flag_regex: vec![flag_regex],
+        transforms: Vec::new(),
         test_flag: Some("PNFP4DKBOV6BTYL9YFGBQ9006582ADC=".into()),
         submitter: Box::new(HttpSubmitter {
-            token: "196_63fdb1ed9ff2419cc7b000cf7e41982a".to_owned(),
+            url: settings
+                .addr
+                .clone()
+                .unwrap_or_else(|| "http://monitor.ructfe.org/flags".into()),
+            token: settings
+                .token
+                .clone()
+                .unwrap_or_else(|| "196_63fdb1ed9ff2419cc7b000cf7e41982a".into()),
         }),
     }
 }
@@ -1,18 +1,16 @@
-use crate::ctfapi::{CTFApi, Flag, Submitter};
+use crate::ctfapi::{read_verdict, resolve, CTFApi, Flag, Settings, Submitter};
 use regex::bytes::Regex;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::io::{BufReader, Write};
+use std::net::TcpStream;
 use std::time::Duration;
 
-pub struct SaarctfSubmitter;
+pub struct SaarctfSubmitter {
+    addr: String,
+}
 
 impl Submitter for SaarctfSubmitter {
     fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
-        let addr = "submission.ctf.saarland:31337"
-            .to_socket_addrs()
-            .expect("failed to resolve submission server")
-            .next()
-            .expect("failed to resovle submission server");
+        let addr = resolve(&self.addr)?;
         let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(1))?;
         let mut data = Vec::new();
         for flag in batch {
@@ -22,25 +20,29 @@ impl Submitter for SaarctfSubmitter {
         stream.write_all(&data)?;
         let mut reader = BufReader::new(stream);
         for flag in batch {
-            let mut status = String::new();
-            let size = reader.read_line(&mut status)?;
-            status.truncate(size);
-            if status.ends_with('\n') {
-                status.truncate(size - 1);
-            }
-            flag.set_verdict(status);
+            flag.set_verdict(read_verdict(&mut reader)?);
         }
         Ok(())
     }
+
+    fn check(&self) -> std::io::Result<()> {
+        resolve(&self.addr).map(|_| ())
+    }
 }
 
-pub fn ctfapi() -> CTFApi {
+pub fn ctfapi(settings: &Settings) -> CTFApi {
     let flag_regex = Regex::new(r"SAAR\{[A-Za-z0-9-_]{32}\}").unwrap();
 
     CTFApi {
         name: "saarctf".into(),
-        flag_regex,
-        submitter: Box::new(SaarctfSubmitter),
+        flag_regex: vec![flag_regex],
+        transforms: Vec::new(),
+        submitter: Box::new(SaarctfSubmitter {
+            addr: settings
+                .addr
+                .clone()
+                .unwrap_or_else(|| "submission.ctf.saarland:31337".into()),
+        }),
         test_flag: Some("SAAR{TESTTESTTESTTESTTESTTESTTESTTEST}".into()),
     }
 }
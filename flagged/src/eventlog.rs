@@ -0,0 +1,582 @@
+//! `flagged events`: summarize or replay recorded events (JSON lines, e.g. from `--events jsonl:PATH`).
+
+use crate::ctfapi::VerdictClass;
+use crate::events::{Event, EventPayload, Publisher, Relay, SinkSpec};
+
+use chrono::{DateTime, Utc};
+use clap::{Args, Subcommand};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Args, Debug)]
+pub struct EventsOpts {
+    #[clap(subcommand)]
+    command: EventsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum EventsCommand {
+    /// Summarize recorded events per session, interval and target
+    Summary {
+        /// JSONL file with one event per line, or - for stdin
+        file: String,
+        /// Print the summaries as JSON lines
+        #[clap(long = "json")]
+        json: bool,
+    },
+    /// Publish recorded events to sinks, keeping their original timing
+    Replay {
+        /// JSONL file with one event per line, or - for stdin
+        file: String,
+        /// Sink to publish to, see --events. Can be passed multiple times
+        #[clap(long = "to", required = true)]
+        to: Vec<SinkSpec>,
+        /// Playback speed, e.g. 10 for ten times faster. 0 publishes as fast as possible
+        #[clap(long = "speed", default_value = "1")]
+        speed: f64,
+    },
+}
+
+/// Reads events, skipping (and reporting) lines that don't parse.
+fn read(file: &str) -> Result<impl Iterator<Item = Event>, flagged_events::Error> {
+    let subscriber = flagged_events::Subscriber::read_jsonl(file)?;
+    let file = file.to_string();
+    Ok(subscriber.filter_map(move |event| match event {
+        Ok(event) => Some(event),
+        Err(err @ flagged_events::Error::Decode { .. }) => {
+            tracing::warn!("{}:{}", file, err);
+            None
+        }
+        Err(err) => {
+            tracing::warn!("{}: {}", file, err);
+            None
+        }
+    }))
+}
+
+#[derive(Default)]
+struct Stats {
+    runs: u64,
+    timeouts: u64,
+    /// Total runtime of finished runs, in seconds
+    runtime: f64,
+    finished: u64,
+    flags: u64,
+    verdicts: u64,
+    accepted: u64,
+    /// Flags flagged gave up on without a verdict
+    dropped: u64,
+}
+
+impl Stats {
+    fn record(&mut self, payload: &EventPayload, runtime: f64) {
+        match payload {
+            EventPayload::RunStart(_) => self.runs += 1,
+            EventPayload::RunTimeout(_) => self.finish_run(runtime, true),
+            EventPayload::RunExit { .. } => self.finish_run(runtime, false),
+            // only emitted for unique flags, also in older recordings
+            EventPayload::FlagPending { .. } => self.flags += 1,
+            EventPayload::FlagVerdict { verdict, .. } => self.verdict(verdict),
+            EventPayload::FlagDropped { .. } => self.dropped += 1,
+            _ => {}
+        }
+    }
+
+    fn finish_run(&mut self, runtime: f64, timeout: bool) {
+        self.finished += 1;
+        self.runtime += runtime;
+        if timeout {
+            self.timeouts += 1;
+        }
+    }
+
+    fn verdict(&mut self, verdict: &str) {
+        // before version 3, expired and unanswered flags got made-up verdicts
+        if verdict.starts_with("[flagged]") {
+            self.dropped += 1;
+            return;
+        }
+        self.verdicts += 1;
+        if VerdictClass::of(verdict) == VerdictClass::Accepted {
+            self.accepted += 1;
+        }
+    }
+
+    fn avg_runtime(&self) -> Option<f64> {
+        if self.finished == 0 {
+            return None;
+        }
+        Some(self.runtime / self.finished as f64)
+    }
+
+    fn accepted_ratio(&self) -> Option<f64> {
+        if self.verdicts == 0 {
+            return None;
+        }
+        Some(self.accepted as f64 / self.verdicts as f64)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "runs": self.runs,
+            "timeouts": self.timeouts,
+            "avg_runtime": self.avg_runtime(),
+            "flags": self.flags,
+            "verdicts": self.verdicts,
+            "accepted": self.accepted,
+            "accepted_ratio": self.accepted_ratio(),
+            "dropped": self.dropped,
+        })
+    }
+
+    fn describe(&self) -> String {
+        let mut out = format!(
+            "runs {}, timeouts {}, flags {}, accepted {}/{}",
+            self.runs, self.timeouts, self.flags, self.accepted, self.verdicts
+        );
+        if let Some(ratio) = self.accepted_ratio() {
+            out += &format!(" ({:.1}%)", ratio * 100.0);
+        }
+        if self.dropped > 0 {
+            out += &format!(", dropped {}", self.dropped);
+        }
+        if let Some(runtime) = self.avg_runtime() {
+            out += &format!(", avg runtime {:.2}s", runtime);
+        }
+        out
+    }
+}
+
+struct SessionSummary {
+    hostname: Option<String>,
+    path: Option<String>,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    intervals: Vec<IntervalSummary>,
+    stats: Stats,
+}
+
+struct IntervalSummary {
+    /// Starting at 1. Counted by the summary for version 1 recordings
+    index: u64,
+    /// Missing without tick alignment and in version 1 recordings
+    round: Option<u64>,
+    started_at: DateTime<Utc>,
+    stats: Stats,
+}
+
+impl IntervalSummary {
+    fn describe(&self) -> String {
+        match self.round {
+            Some(round) => format!("round {}", round),
+            None => format!("interval {}", self.index),
+        }
+    }
+}
+
+struct RunInfo {
+    session: Uuid,
+    // index into the session's intervals, if the run started during one
+    interval: Option<usize>,
+    key: String,
+    started_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct TargetSummary {
+    stats: Stats,
+    /// Session, index into its intervals and time of the first accepted flag
+    first_success: Option<(Uuid, usize, DateTime<Utc>)>,
+}
+
+#[derive(Default)]
+struct Summary {
+    order: Vec<Uuid>,
+    sessions: HashMap<Uuid, SessionSummary>,
+    targets: BTreeMap<String, TargetSummary>,
+    runs: HashMap<Uuid, RunInfo>,
+}
+
+impl Summary {
+    fn record(&mut self, event: Event) {
+        let session = match self.sessions.get_mut(&event.session_id) {
+            Some(session) => session,
+            None => {
+                self.order.push(event.session_id);
+                self.sessions
+                    .entry(event.session_id)
+                    .or_insert(SessionSummary {
+                        hostname: None,
+                        path: None,
+                        first: event.timestamp,
+                        last: event.timestamp,
+                        intervals: Vec::new(),
+                        stats: Stats::default(),
+                    })
+            }
+        };
+        session.last = session.last.max(event.timestamp);
+
+        let run_id = match &event.payload {
+            EventPayload::SessionAnnouncement(announcement) => {
+                session.hostname = Some(announcement.hostname.clone());
+                session.path = Some(announcement.path.clone());
+                return;
+            }
+            EventPayload::IntervalStart(interval) => {
+                let index = session.intervals.len() as u64 + 1;
+                session.intervals.push(IntervalSummary {
+                    index: interval.as_ref().map_or(index, |interval| interval.index),
+                    round: interval.as_ref().and_then(|interval| interval.round),
+                    started_at: event.timestamp,
+                    stats: Stats::default(),
+                });
+                return;
+            }
+            EventPayload::IntervalEnd(_)
+            | EventPayload::SessionEnd { .. }
+            | EventPayload::SubmitBatch { .. } => return,
+            EventPayload::RunStart(run) => {
+                let interval = session.intervals.len().checked_sub(1);
+                self.runs.insert(
+                    run.id,
+                    RunInfo {
+                        session: event.session_id,
+                        interval,
+                        key: run.key.clone(),
+                        started_at: event.timestamp,
+                    },
+                );
+                run.id
+            }
+            EventPayload::RunTimeout(run)
+            | EventPayload::RunSpawnFailed { run, .. }
+            | EventPayload::RunKillFailed { run, .. }
+            | EventPayload::RunStats { run, .. }
+            | EventPayload::RunExit { run, .. }
+            | EventPayload::StdoutLine { run, .. }
+            | EventPayload::StderrLine { run, .. }
+            | EventPayload::FlagMatch { run, .. }
+            | EventPayload::FlagPending { run, .. }
+            | EventPayload::FlagVerdict { run, .. }
+            | EventPayload::FlagDropped { run, .. } => run.id,
+            // newer event types
+            _ => return,
+        };
+        let run = match self.runs.get(&run_id) {
+            Some(run) => run,
+            // e.g. the test flag, or a recording that started mid-run
+            None => return,
+        };
+        let target = self.targets.entry(run.key.clone()).or_default();
+        if let EventPayload::FlagVerdict { verdict, .. } = &event.payload {
+            let accepted = VerdictClass::of(verdict) == VerdictClass::Accepted;
+            if let (true, None, Some(idx)) = (accepted, target.first_success, run.interval) {
+                target.first_success = Some((run.session, idx, event.timestamp));
+            }
+        }
+
+        let runtime = (event.timestamp - run.started_at).num_milliseconds() as f64 / 1000.0;
+        session.stats.record(&event.payload, runtime);
+        target.stats.record(&event.payload, runtime);
+        if let Some(interval) = run.interval.and_then(|idx| session.intervals.get_mut(idx)) {
+            interval.stats.record(&event.payload, runtime);
+        }
+    }
+
+    fn print(&self) {
+        for session_id in &self.order {
+            let session = &self.sessions[session_id];
+            println!(
+                "Session {} on {}:{}",
+                session_id,
+                session.hostname.as_deref().unwrap_or("?"),
+                session.path.as_deref().unwrap_or("?"),
+            );
+            println!(
+                "| {} - {} ({} intervals)",
+                session.first,
+                session.last,
+                session.intervals.len()
+            );
+            println!("| {}", session.stats.describe());
+            for interval in &session.intervals {
+                let round = match interval.round {
+                    Some(round) => format!(" (round {})", round),
+                    None => String::new(),
+                };
+                println!(
+                    "| interval {}{} at {}: {}",
+                    interval.index,
+                    round,
+                    interval.started_at.format("%H:%M:%S"),
+                    interval.stats.describe()
+                );
+            }
+        }
+        println!("Targets:");
+        for (key, target) in &self.targets {
+            let first_success = match target.first_success {
+                Some((session, idx, at)) => {
+                    format!(
+                        "{} ({})",
+                        self.sessions[&session].intervals[idx].describe(),
+                        at
+                    )
+                }
+                None => "never".into(),
+            };
+            println!(
+                "| {}: {}, first accepted flag: {}",
+                key,
+                target.stats.describe(),
+                first_success
+            );
+        }
+    }
+
+    fn print_json(&self) {
+        for session_id in &self.order {
+            let session = &self.sessions[session_id];
+            let mut line = session.stats.to_json();
+            line["type"] = json!("session");
+            line["session_id"] = json!(session_id);
+            line["hostname"] = json!(session.hostname);
+            line["path"] = json!(session.path);
+            line["first"] = json!(session.first);
+            line["last"] = json!(session.last);
+            line["intervals"] = json!(session.intervals.len());
+            println!("{}", line);
+            for interval in &session.intervals {
+                let mut line = interval.stats.to_json();
+                line["type"] = json!("interval");
+                line["session_id"] = json!(session_id);
+                line["interval"] = json!(interval.index);
+                line["round"] = json!(interval.round);
+                line["started_at"] = json!(interval.started_at);
+                println!("{}", line);
+            }
+        }
+        for (key, target) in &self.targets {
+            let mut line = target.stats.to_json();
+            line["type"] = json!("target");
+            line["key"] = json!(key);
+            line["first_success"] = match target.first_success {
+                Some((session_id, idx, at)) => {
+                    let interval = &self.sessions[&session_id].intervals[idx];
+                    json!({
+                        "session_id": session_id,
+                        "interval": interval.index,
+                        "round": interval.round,
+                        "timestamp": at,
+                    })
+                }
+                None => json!(null),
+            };
+            println!("{}", line);
+        }
+    }
+}
+
+async fn replay(
+    file: &str,
+    to: Vec<SinkSpec>,
+    speed: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let publishers = to
+        .iter()
+        .map(Publisher::open)
+        .collect::<Result<Vec<_>, _>>()?;
+    let relay = Relay::open(publishers);
+    let mut previous: Option<DateTime<Utc>> = None;
+    let (mut count, mut unknown) = (0, 0);
+    for event in read(file)? {
+        // recorded by a newer flagged, these can't be written again
+        if let EventPayload::Unknown = event.payload {
+            unknown += 1;
+            continue;
+        }
+        if let Some(previous) = previous.filter(|_| speed > 0.0) {
+            let delay = (event.timestamp - previous).to_std().unwrap_or_default();
+            tokio::time::sleep(Duration::from_secs_f64(delay.as_secs_f64() / speed)).await;
+        }
+        previous = Some(event.timestamp);
+        relay.send(event).await;
+        count += 1;
+    }
+    relay.flush().await;
+    println!("Replayed {} events", count);
+    if unknown > 0 {
+        println!("Skipped {} events of unknown types", unknown);
+    }
+    Ok(())
+}
+
+pub async fn run(opts: EventsOpts) -> Result<(), Box<dyn std::error::Error>> {
+    match opts.command {
+        EventsCommand::Summary { file, json } => {
+            let mut summary = Summary::default();
+            for event in read(&file)? {
+                summary.record(event);
+            }
+            if json {
+                summary.print_json();
+            } else {
+                summary.print();
+            }
+        }
+        EventsCommand::Replay { file, to, speed } => {
+            if !(speed >= 0.0 && speed.is_finite()) {
+                return Err("--speed must be a non-negative number".into());
+            }
+            replay(&file, to, speed).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Interval, Run};
+    use chrono::TimeZone;
+
+    fn event(session_id: Uuid, secs: i64, payload: EventPayload) -> Event {
+        Event {
+            version: crate::events::SCHEMA_VERSION,
+            session_id,
+            timestamp: Utc.timestamp(1_600_000_000 + secs, 0),
+            payload,
+        }
+    }
+
+    #[test]
+    fn summarizes_intervals_and_targets() {
+        let session = Uuid::new_v4();
+        let run = |key: &str| Run {
+            id: Uuid::new_v4(),
+            key: key.into(),
+            target: HashMap::new(),
+        };
+        let (a1, b1, a2) = (run("a"), run("b"), run("a"));
+        let verdict = |run: &Run, verdict: &str| EventPayload::FlagVerdict {
+            run: run.clone(),
+            flag: "FLAG".into(),
+            verdict: verdict.into(),
+        };
+        let found = |run: &Run, is_unique| EventPayload::FlagMatch {
+            run: run.clone(),
+            flag: "FLAG".into(),
+            is_unique,
+        };
+        let pending = |run: &Run| EventPayload::FlagPending {
+            run: run.clone(),
+            flag: "FLAG".into(),
+        };
+        let exit = |run: &Run| EventPayload::RunExit {
+            run: run.clone(),
+            exit_code: Some(0),
+        };
+
+        let mut summary = Summary::default();
+        for event in [
+            event(session, 0, EventPayload::IntervalStart(None)),
+            event(session, 0, EventPayload::RunStart(a1.clone())),
+            event(session, 0, EventPayload::RunStart(b1.clone())),
+            event(session, 1, found(&a1, true)),
+            event(session, 1, pending(&a1)),
+            event(session, 2, exit(&a1)),
+            event(session, 4, EventPayload::RunTimeout(b1.clone())),
+            event(session, 5, verdict(&a1, "[ERR] Invalid flag")),
+            event(session, 5, EventPayload::IntervalEnd(None)),
+            event(session, 10, EventPayload::IntervalStart(None)),
+            event(session, 10, EventPayload::RunStart(a2.clone())),
+            event(session, 11, found(&a2, true)),
+            event(session, 11, pending(&a2)),
+            event(session, 11, found(&a2, false)),
+            event(session, 13, exit(&a2)),
+            event(session, 14, verdict(&a2, "[OK] Accepted")),
+            event(
+                session,
+                14,
+                EventPayload::FlagDropped {
+                    run: a2.clone(),
+                    flag: "FLAG".into(),
+                    reason: "expired".into(),
+                },
+            ),
+            // version 2 recordings
+            event(session, 14, verdict(&a2, "[flagged] no verdict received")),
+            event(session, 14, EventPayload::IntervalEnd(None)),
+        ] {
+            summary.record(event);
+        }
+
+        let stats = &summary.sessions[&session].stats;
+        assert_eq!((stats.runs, stats.timeouts, stats.flags), (3, 1, 2));
+        assert_eq!(stats.accepted_ratio(), Some(0.5));
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.avg_runtime(), Some(3.0));
+
+        let intervals = &summary.sessions[&session].intervals;
+        assert_eq!(intervals.len(), 2);
+        assert_eq!(
+            (intervals[0].stats.runs, intervals[0].stats.accepted),
+            (2, 0)
+        );
+        assert_eq!(
+            (intervals[1].stats.runs, intervals[1].stats.accepted),
+            (1, 1)
+        );
+
+        let a = &summary.targets["a"];
+        let (_, idx, _) = a.first_success.unwrap();
+        assert_eq!(intervals[idx].describe(), "interval 2");
+        assert_eq!(a.stats.avg_runtime(), Some(2.5));
+        assert!(summary.targets["b"].first_success.is_none());
+    }
+
+    #[test]
+    fn first_success_reports_the_round() {
+        let session = Uuid::new_v4();
+        let run = Run {
+            id: Uuid::new_v4(),
+            key: "a".into(),
+            target: HashMap::new(),
+        };
+        let interval = |index, round| {
+            EventPayload::IntervalStart(Some(Interval {
+                id: Uuid::new_v4(),
+                index,
+                round,
+            }))
+        };
+
+        let mut summary = Summary::default();
+        for event in [
+            event(session, 0, interval(1, None)),
+            event(session, 10, interval(2, Some(17))),
+            event(session, 20, interval(3, Some(19))),
+            event(session, 20, EventPayload::RunStart(run.clone())),
+            event(
+                session,
+                21,
+                EventPayload::FlagVerdict {
+                    run: run.clone(),
+                    flag: "FLAG".into(),
+                    verdict: "[OK] Accepted".into(),
+                },
+            ),
+        ] {
+            summary.record(event);
+        }
+
+        let intervals = &summary.sessions[&session].intervals;
+        assert_eq!(intervals[0].describe(), "interval 1");
+        let (_, idx, _) = summary.targets["a"].first_success.unwrap();
+        assert_eq!((intervals[idx].index, intervals[idx].round), (3, Some(19)));
+        assert_eq!(intervals[idx].describe(), "round 19");
+    }
+}
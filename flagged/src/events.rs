@@ -1,61 +1,474 @@
-use chrono::DateTime;
 use redis::Commands;
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
+pub use flagged_events::{
+    Event, EventPayload, Interval, ResourceUsage, Run, SessionAnnouncement, StreamSpec,
+    SCHEMA_VERSION,
+};
+
+use crate::metrics::metrics;
+
+const REDIS_TIMEOUT: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Events waiting for the background writer. Further events are dropped
+const EVENT_BUFFER_SIZE: usize = 16384;
+// Slow socket clients are disconnected rather than slowing down exploits
+const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+static DROPPED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Where events end up. Several sinks can be used at the same time.
+#[derive(Clone)]
+pub enum Publisher {
+    /// Redis pub/sub on the `events` channel. Events are lost while nobody is subscribed
+    Redis(redis::Client),
+    /// Redis stream, trimmed to roughly `maxlen` entries
+    RedisStream {
+        client: redis::Client,
+        key: String,
+        maxlen: usize,
+    },
+    /// Appends JSON lines to a file, in the format `statserver/events.jsonl` uses
+    Jsonl {
+        path: String,
+        file: Arc<Mutex<File>>,
+    },
+    /// Sends JSON lines to every client connected to a Unix socket
+    UnixSocket {
+        path: String,
+        clients: Arc<Mutex<Vec<UnixStream>>>,
+    },
+    /// Prints JSON lines to stdout
+    Stdout,
+    Channel(mpsc::UnboundedSender<Event>),
+}
+
+enum Connection {
+    Redis(redis::Connection),
+    RedisStream {
+        connection: redis::Connection,
+        key: String,
+        maxlen: usize,
+    },
+    Jsonl(Arc<Mutex<File>>),
+    UnixSocket(Arc<Mutex<Vec<UnixStream>>>),
+    Stdout,
+}
+
+impl Connection {
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        match self {
+            Connection::Redis(connection) => connection
+                .publish::<_, _, ()>("events", line)
+                .map_err(|err| err.to_string()),
+            Connection::RedisStream {
+                connection,
+                key,
+                maxlen,
+            } => redis::cmd("XADD")
+                .arg(&*key)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(*maxlen)
+                .arg("*")
+                .arg("event")
+                .arg(line)
+                .query::<()>(connection)
+                .map_err(|err| err.to_string()),
+            Connection::Jsonl(file) => {
+                writeln!(file.lock().unwrap(), "{}", line).map_err(|err| err.to_string())
+            }
+            Connection::UnixSocket(clients) => {
+                let mut clients = clients.lock().unwrap();
+                clients.retain_mut(|client| writeln!(client, "{}", line).is_ok());
+                Ok(())
+            }
+            // a closed stdout (e.g. `| head`) is a broken sink, not a reason to panic
+            Connection::Stdout => {
+                writeln!(std::io::stdout().lock(), "{}", line).map_err(|err| err.to_string())
+            }
+        }
+    }
+}
+
+/// A sink as passed to `--events`. Parsing only checks the syntax, the sink is opened by `Publisher::open`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkSpec {
+    Redis(String),
+    RedisStream(StreamSpec),
+    Jsonl(String),
+    UnixSocket(String),
+    Stdout,
+}
+
+impl std::str::FromStr for SinkSpec {
+    type Err = String;
+
+    /// Parses
+    /// - `redis://host[:port][/db]` for pub/sub,
+    /// - `redis-stream://host[:port][/db][?key=events&maxlen=1000000]`, see [`StreamSpec`],
+    /// - `jsonl:PATH`, `unix:PATH` (relative to the current directory, not the exploit folder) or `stdout`.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec == "stdout" {
+            Ok(SinkSpec::Stdout)
+        } else if let Some(path) = spec.strip_prefix("jsonl:") {
+            Ok(SinkSpec::Jsonl(path.to_string()))
+        } else if let Some(path) = spec.strip_prefix("unix:") {
+            Ok(SinkSpec::UnixSocket(path.to_string()))
+        } else if spec.starts_with("redis-stream://") {
+            Ok(SinkSpec::RedisStream(spec.parse()?))
+        } else if spec.starts_with("redis://") || spec.starts_with("rediss://") {
+            redis::parse_redis_url(spec).ok_or_else(|| format!("invalid redis url {:?}", spec))?;
+            Ok(SinkSpec::Redis(spec.to_string()))
+        } else {
+            Err(format!(
+                "unknown event sink {:?}, expected redis://, redis-stream://, jsonl:PATH, unix:PATH or stdout",
+                spec
+            ))
+        }
+    }
+}
+
+impl Publisher {
+    /// Opens the sink: creates JSONL files and binds Unix sockets. Redis is connected to by the background writer.
+    pub fn open(spec: &SinkSpec) -> Result<Publisher, String> {
+        let client = |url: &str| redis::Client::open(url).map_err(|err| err.to_string());
+        Ok(match spec {
+            SinkSpec::Stdout => Publisher::Stdout,
+            SinkSpec::Jsonl(path) => {
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| format!("{}: {}", path, err))?;
+                Publisher::Jsonl {
+                    path: path.clone(),
+                    file: Arc::new(Mutex::new(file)),
+                }
+            }
+            SinkSpec::UnixSocket(path) => {
+                Self::listen(path).map_err(|err| format!("{}: {}", path, err))?
+            }
+            SinkSpec::RedisStream(stream) => Publisher::RedisStream {
+                client: client(&stream.url)?,
+                key: stream.key.clone(),
+                maxlen: stream.maxlen,
+            },
+            SinkSpec::Redis(url) => Publisher::Redis(client(url)?),
+        })
+    }
+
+    /// Binds a Unix socket and accepts clients in the background. Replaces stale sockets.
+    fn listen(path: &str) -> std::io::Result<Publisher> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT)).is_ok() {
+                    accepted.lock().unwrap().push(stream);
+                }
+            }
+        });
+        Ok(Publisher::UnixSocket {
+            path: path.to_string(),
+            clients,
+        })
+    }
+
+    /// Connects to external sinks. In-process channels are not handled by the background writer.
+    fn connect(&self) -> Result<Connection, String> {
+        let redis_connection = |client: &redis::Client| {
+            client
+                .get_connection_with_timeout(REDIS_TIMEOUT)
+                .map_err(|err| err.to_string())
+        };
+        Ok(match self {
+            Publisher::Redis(client) => Connection::Redis(redis_connection(client)?),
+            Publisher::RedisStream {
+                client,
+                key,
+                maxlen,
+            } => Connection::RedisStream {
+                connection: redis_connection(client)?,
+                key: key.clone(),
+                maxlen: *maxlen,
+            },
+            Publisher::Jsonl { file, .. } => Connection::Jsonl(file.clone()),
+            Publisher::UnixSocket { clients, .. } => Connection::UnixSocket(clients.clone()),
+            Publisher::Stdout => Connection::Stdout,
+            Publisher::Channel(_) => unreachable!("channels are published to directly"),
+        })
+    }
+}
+
+impl std::fmt::Debug for Publisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Publisher::Redis(client) => write!(f, "Redis({:?})", client.get_connection_info().addr),
+            Publisher::RedisStream { key, maxlen, .. } => {
+                write!(f, "RedisStream({:?}, maxlen {})", key, maxlen)
+            }
+            Publisher::Jsonl { path, .. } => write!(f, "Jsonl({:?})", path),
+            Publisher::UnixSocket { path, .. } => write!(f, "UnixSocket({:?})", path),
+            Publisher::Stdout => write!(f, "Stdout"),
+            Publisher::Channel(_) => write!(f, "Channel"),
+        }
+    }
+}
+
+enum Message {
+    Event(Event),
+    Flush(oneshot::Sender<()>),
+}
+
+/// A sink served by the background writer. Broken connections are re-established after a delay,
+/// events that arrive in the meantime are dropped.
+struct Sink {
+    publisher: Publisher,
+    /// `sink` label of `flagged_events_dropped_total`
+    label: String,
+    connection: Option<Connection>,
+    retry_at: Option<Instant>,
+}
+
+impl Sink {
+    fn send(&mut self, line: &str) {
+        if self.connection.is_none() {
+            if self
+                .retry_at
+                .is_some_and(|retry_at| Instant::now() < retry_at)
+            {
+                return self.dropped();
+            }
+            match self.publisher.connect() {
+                Ok(connection) => {
+                    if self.retry_at.take().is_some() {
+                        tracing::info!("events: reconnected to {:?}", self.publisher);
+                    }
+                    self.connection = Some(connection);
+                }
+                Err(err) => return self.failed(err),
+            }
+        }
+        if let Err(err) = self.connection.as_mut().unwrap().send(line) {
+            self.failed(err);
+        }
+    }
+
+    fn failed(&mut self, err: String) {
+        self.dropped();
+        tracing::warn!(
+            "events: {:?} failed, retrying in {:?}: {}",
+            self.publisher,
+            RECONNECT_DELAY,
+            err
+        );
+        self.connection = None;
+        self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+    }
+
+    fn dropped(&self) {
+        metrics().events_dropped.inc(&[&self.label]);
+    }
+}
+
+/// Hands events to in-process channels directly and to external sinks through a bounded
+/// buffer drained by a background thread. Events are dropped (and counted) if the buffer is
+/// full, so slow or broken sinks never hold up exploits.
+#[derive(Clone, Default)]
+struct Emitter {
+    channels: Vec<mpsc::UnboundedSender<Event>>,
+    writer: Option<mpsc::Sender<Message>>,
+    /// Labels of the sinks behind `writer`, every one of them misses events dropped from the buffer
+    sink_labels: Arc<Vec<String>>,
+}
+
+impl Emitter {
+    fn start(publishers: Vec<Publisher>) -> Self {
+        Self::with_buffer(publishers, EVENT_BUFFER_SIZE)
+    }
+
+    fn with_buffer(publishers: Vec<Publisher>, buffer_size: usize) -> Self {
+        let mut channels = Vec::new();
+        let mut sinks = Vec::new();
+        for publisher in publishers {
+            match publisher {
+                Publisher::Channel(tx) => channels.push(tx),
+                publisher => sinks.push(Sink {
+                    label: format!("{:?}", publisher),
+                    publisher,
+                    connection: None,
+                    retry_at: None,
+                }),
+            }
+        }
+        let sink_labels = Arc::new(sinks.iter().map(|sink| sink.label.clone()).collect());
+        let writer = if sinks.is_empty() {
+            None
+        } else {
+            let (tx, rx) = mpsc::channel(buffer_size);
+            std::thread::spawn(move || Self::write(rx, sinks));
+            Some(tx)
+        };
+        Emitter {
+            channels,
+            writer,
+            sink_labels,
+        }
+    }
+
+    fn write(mut rx: mpsc::Receiver<Message>, mut sinks: Vec<Sink>) {
+        while let Some(message) = rx.blocking_recv() {
+            match message {
+                Message::Event(event) => {
+                    let line = match serde_json::to_string(&event) {
+                        Ok(line) => line,
+                        Err(err) => {
+                            tracing::error!("events: failed to serialize event: {:?}", err);
+                            continue;
+                        }
+                    };
+                    for sink in &mut sinks {
+                        sink.send(&line);
+                    }
+                }
+                Message::Flush(ack) => {
+                    let _ = ack.send(());
+                }
+            }
+        }
+    }
+
+    fn publish(&self, session_id: Uuid, payload: EventPayload) {
+        if self.channels.is_empty() && self.writer.is_none() {
+            return;
+        }
+        let event = Event {
+            version: SCHEMA_VERSION,
+            payload,
+            session_id,
+            timestamp: chrono::offset::Utc::now(),
+        };
+        for tx in &self.channels {
+            let _ = tx.send(event.clone());
+        }
+        if let Some(writer) = &self.writer {
+            if writer.try_send(Message::Event(event)).is_err() {
+                let dropped = DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+                for label in self.sink_labels.iter() {
+                    metrics().events_dropped.inc(&[label]);
+                }
+                if dropped.is_power_of_two() {
+                    tracing::warn!(
+                        "event sinks can't keep up, dropped {} event(s) so far",
+                        dropped
+                    );
+                }
+            }
+        }
+    }
+
+    /// Publishes an existing event, waiting for buffer space instead of dropping it.
+    async fn forward(&self, event: Event) {
+        for tx in &self.channels {
+            let _ = tx.send(event.clone());
+        }
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(Message::Event(event)).await;
+        }
+    }
+
+    /// Waits until all events published so far have been handed to the sinks.
+    async fn flush(&self) {
+        if let Some(writer) = &self.writer {
+            let (tx, rx) = oneshot::channel();
+            if writer.send(Message::Flush(tx)).await.is_ok() {
+                let _ = rx.await;
+            }
+        }
+    }
+}
+
+/// Publishes recorded events unchanged, e.g. for `flagged events replay`.
+pub struct Relay {
+    emitter: Emitter,
+}
+
+impl Relay {
+    pub fn open(publishers: Vec<Publisher>) -> Self {
+        Relay {
+            emitter: Emitter::start(publishers),
+        }
+    }
+
+    pub async fn send(&self, event: Event) {
+        self.emitter.forward(event).await;
+    }
+
+    pub async fn flush(&self) {
+        self.emitter.flush().await;
+    }
+}
+
 pub struct Session {
     session_id: Uuid,
-    redis: Option<redis::Client>,
-    connection: Option<redis::Connection>,
+    emitter: Emitter,
+    /// Index of the current interval, starting at 1
+    interval: u64,
+    interval_id: Uuid,
+    round: Option<u64>,
 }
 
 impl Session {
-    pub fn open(redis: Option<redis::Client>, announcement: SessionAnnouncement) -> Self {
+    pub fn open(publishers: Vec<Publisher>, announcement: SessionAnnouncement) -> Self {
         let session_id = Uuid::new_v4();
-        let mut connection = redis.as_ref().map(|client| {
-            client
-                .get_connection_with_timeout(std::time::Duration::from_secs(1))
-                .expect("unable to connect to redis")
-        });
-        Self::publish(
-            connection.as_mut(),
+        let emitter = Emitter::start(publishers);
+        emitter.publish(
             session_id,
-            EventPayload::SessionAnnouncement(announcement),
+            EventPayload::SessionAnnouncement(Box::new(announcement)),
         );
         Session {
-            connection,
+            emitter,
             session_id,
-            redis,
+            interval: 0,
+            interval_id: Uuid::nil(),
+            round: None,
         }
     }
 
-    fn publish(
-        connection: Option<&mut redis::Connection>,
-        session_id: Uuid,
-        payload: EventPayload,
-    ) {
-        if let Some(connection) = connection {
-            let timestamp = chrono::offset::Utc::now();
-            let event = Event {
-                payload,
-                session_id,
-                timestamp,
-            };
-            let event = serde_json::to_string(&event).expect("failed to serialize event");
-            connection
-                .publish::<_, _, ()>("events", event)
-                .expect("failed to publish message to redis");
+    /// For events that don't belong to a run, e.g. from the submitter
+    pub fn handle(&self) -> SessionHandle {
+        SessionHandle {
+            session_id: self.session_id,
+            emitter: self.emitter.clone(),
         }
     }
 
-    pub fn run_handle(&self, target: &crate::config::Target) -> SessionRunHandle {
-        let connection = self.redis.as_ref().map(|client| {
-            client
-                .get_connection_with_timeout(std::time::Duration::from_secs(1))
-                .expect("unable to connect to redis")
-        });
+    fn publish(&self, payload: EventPayload) {
+        self.emitter.publish(self.session_id, payload);
+    }
+
+    /// Waits until all events published so far have been handed to the sinks.
+    pub async fn flush(&self) {
+        self.emitter.flush().await;
+    }
 
+    pub fn run_handle(&self, target: &crate::config::Target) -> SessionRunHandle {
         let run = Run {
             id: Uuid::new_v4(),
             target: target.env.clone(),
@@ -63,49 +476,117 @@ impl Session {
         };
 
         SessionRunHandle {
-            connection,
+            emitter: self.emitter.clone(),
             session_id: self.session_id,
             run,
+            interval: self.interval,
+            round: self.round,
         }
     }
 
-    pub fn start_interval(&mut self) {
-        Self::publish(
-            self.connection.as_mut(),
-            self.session_id,
-            EventPayload::IntervalStart,
-        )
+    /// Starts the next interval. Flags captured from now on are tagged with it and `round`.
+    pub fn start_interval(&mut self, round: Option<u64>) {
+        self.interval += 1;
+        self.interval_id = Uuid::new_v4();
+        self.round = round;
+        self.publish(EventPayload::IntervalStart(Some(self.current_interval())))
+    }
+
+    /// 0 until the first interval is started
+    pub fn interval(&self) -> u64 {
+        self.interval
     }
 
     pub fn end_interval(&mut self) {
-        Self::publish(
-            self.connection.as_mut(),
+        self.publish(EventPayload::IntervalEnd(Some(self.current_interval())))
+    }
+
+    fn current_interval(&self) -> Interval {
+        Interval {
+            id: self.interval_id,
+            index: self.interval,
+            round: self.round,
+        }
+    }
+
+    /// Announces that no more intervals will be run
+    pub fn end(&mut self) {
+        self.publish(EventPayload::SessionEnd {
+            intervals: self.interval,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionHandle {
+    session_id: Uuid,
+    emitter: Emitter,
+}
+
+impl SessionHandle {
+    #[cfg(test)]
+    pub fn noop() -> Self {
+        SessionHandle {
+            session_id: Uuid::default(),
+            emitter: Emitter::default(),
+        }
+    }
+
+    pub fn submit_batch(
+        &self,
+        flags: usize,
+        verdicts: usize,
+        duration: Duration,
+        error: Option<String>,
+    ) {
+        self.emitter.publish(
             self.session_id,
-            EventPayload::IntervalEnd,
+            EventPayload::SubmitBatch {
+                flags,
+                verdicts,
+                duration: duration.as_secs_f64(),
+                error,
+            },
         )
     }
 }
 
 pub struct SessionRunHandle {
     session_id: Uuid,
-    connection: Option<redis::Connection>,
+    emitter: Emitter,
     run: Run,
+    interval: u64,
+    round: Option<u64>,
 }
 
 impl SessionRunHandle {
     pub fn noop() -> Self {
         SessionRunHandle {
             session_id: Uuid::default(),
-            connection: None,
+            emitter: Emitter::default(),
             run: Run {
                 id: Uuid::default(),
                 target: HashMap::new(),
                 key: "".into(),
             },
+            interval: 0,
+            round: None,
         }
     }
+    pub fn key(&self) -> &str {
+        &self.run.key
+    }
+    pub fn run_id(&self) -> Uuid {
+        self.run.id
+    }
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+    pub fn round(&self) -> Option<u64> {
+        self.round
+    }
     fn publish(&mut self, payload: EventPayload) {
-        Session::publish(self.connection.as_mut(), self.session_id, payload);
+        self.emitter.publish(self.session_id, payload);
     }
     pub fn start(&mut self) {
         self.publish(EventPayload::RunStart(self.run.clone()))
@@ -119,6 +600,25 @@ impl SessionRunHandle {
             exit_code,
         })
     }
+    pub fn spawn_failed(&mut self, error: String) {
+        self.publish(EventPayload::RunSpawnFailed {
+            run: self.run.clone(),
+            error,
+        })
+    }
+    pub fn kill_failed(&mut self, error: String) {
+        self.publish(EventPayload::RunKillFailed {
+            run: self.run.clone(),
+            error,
+        })
+    }
+    pub fn stats(&mut self, wall_time: Duration, rusage: Option<ResourceUsage>) {
+        self.publish(EventPayload::RunStats {
+            run: self.run.clone(),
+            wall_time: wall_time.as_secs_f64(),
+            rusage,
+        })
+    }
     pub fn stdout_line(&mut self, line: String) {
         self.publish(EventPayload::StdoutLine {
             run: self.run.clone(),
@@ -152,61 +652,98 @@ impl SessionRunHandle {
             verdict,
         })
     }
+    pub fn flag_dropped(&mut self, flag: String, reason: String) {
+        self.publish(EventPayload::FlagDropped {
+            run: self.run.clone(),
+            flag,
+            reason,
+        })
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SessionAnnouncement {
-    pub hostname: String,
-    pub path: String,
-    pub config: crate::config::Config,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Run {
-    id: Uuid,
-    key: String,
-    target: HashMap<String, String>,
-}
+    #[tokio::test]
+    async fn jsonl_and_unix_socket_sinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let jsonl = dir.path().join("events.jsonl");
+        let socket = dir.path().join("events.sock");
+        let publishers = vec![
+            Publisher::open(&SinkSpec::Jsonl(jsonl.display().to_string())).unwrap(),
+            Publisher::open(&SinkSpec::UnixSocket(socket.display().to_string())).unwrap(),
+        ];
+        let client = UnixStream::connect(&socket).unwrap();
+        // wait for the client to be accepted
+        while matches!(&publishers[1], Publisher::UnixSocket { clients, .. } if clients.lock().unwrap().is_empty())
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Event {
-    session_id: Uuid,
-    timestamp: DateTime<chrono::offset::Utc>,
-    payload: EventPayload,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "t", content = "c")]
-pub enum EventPayload {
-    SessionAnnouncement(SessionAnnouncement),
-    IntervalStart,
-    IntervalEnd,
-    RunStart(Run),
-    RunTimeout(Run),
-    RunExit {
-        run: Run,
-        exit_code: Option<i32>,
-    },
-    StdoutLine {
-        run: Run,
-        line: String,
-    },
-    StderrLine {
-        run: Run,
-        line: String,
-    },
-    FlagMatch {
-        run: Run,
-        flag: String,
-        is_unique: bool,
-    },
-    FlagPending {
-        run: Run,
-        flag: String,
-    },
-    FlagVerdict {
-        run: Run,
-        flag: String,
-        verdict: String,
-    },
+        let config = serde_json::from_value(serde_json::json!({
+            "command": ["true"], "interval": 1, "timeout": 1, "concurrency": 1,
+        }))
+        .unwrap();
+        let announcement = SessionAnnouncement {
+            hostname: "test".into(),
+            path: "test".into(),
+            config,
+        };
+        let mut session = Session::open(publishers, announcement);
+        session.start_interval(None);
+        session.flush().await;
+
+        let lines = std::fs::read_to_string(&jsonl).unwrap();
+        let from_file = lines
+            .lines()
+            .map(|line| serde_json::from_str::<Event>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(from_file.len(), 2);
+        assert!(matches!(
+            from_file[0].payload,
+            EventPayload::SessionAnnouncement(_)
+        ));
+        assert!(matches!(
+            &from_file[1].payload,
+            EventPayload::IntervalStart(Some(Interval { index: 1, .. }))
+        ));
+        assert_eq!(from_file[1].version, SCHEMA_VERSION);
+
+        let mut reader = BufReader::new(client);
+        for expected in lines.lines() {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line.trim_end(), expected);
+        }
+
+        assert!(matches!(
+            "redis-stream://localhost?maxlen=10&key=ev".parse(),
+            Ok(SinkSpec::RedisStream(StreamSpec { maxlen: 10, .. }))
+        ));
+        assert_eq!(
+            "jsonl:events.jsonl".parse(),
+            Ok(SinkSpec::Jsonl("events.jsonl".into()))
+        );
+        assert!("redis-stream://localhost?len=10"
+            .parse::<SinkSpec>()
+            .is_err());
+        assert!("redis://:::".parse::<SinkSpec>().is_err());
+        assert!("kafka://localhost".parse::<SinkSpec>().is_err());
+    }
+
+    #[tokio::test]
+    async fn counts_events_sinks_missed() {
+        // writes to /dev/full fail, so the sink is always waiting to reconnect
+        let failing = Publisher::open(&SinkSpec::Jsonl("/dev/full".into())).unwrap();
+        let label = format!("{:?}", failing);
+        let emitter = Emitter::with_buffer(vec![failing], 1);
+        for _ in 0..100 {
+            emitter.publish(Uuid::nil(), EventPayload::SessionEnd { intervals: 0 });
+        }
+        emitter.flush().await;
+        // whether dropped from the buffer or by the sink, every event is counted once
+        assert_eq!(metrics().events_dropped.get(&[&label]), 100.0);
+    }
 }
@@ -0,0 +1,170 @@
+use crate::ctfapi::CTFApi;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Per-exploit adjustments to the ctfapi's flag format.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FlagFormat {
+    /// Replaces the ctfapi's flag regex
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regex: Vec<String>,
+    /// Matched in addition to the ctfapi's (or overridden) flag regex
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_regex: Vec<String>,
+    /// Applied to every matched flag, in order, before deduplication
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    Trim,
+    Uppercase,
+    Lowercase,
+    TruncateGraphemes(usize),
+    StripPrefix(String),
+    StripSuffix(String),
+    Replace { from: String, to: String },
+}
+
+impl Transform {
+    pub fn apply(&self, flag: String) -> String {
+        match self {
+            Transform::Trim => flag.trim().to_string(),
+            Transform::Uppercase => flag.to_uppercase(),
+            Transform::Lowercase => flag.to_lowercase(),
+            Transform::TruncateGraphemes(n) => flag.graphemes(true).take(*n).collect(),
+            Transform::StripPrefix(prefix) => match flag.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.to_string(),
+                None => flag,
+            },
+            Transform::StripSuffix(suffix) => match flag.strip_suffix(suffix.as_str()) {
+                Some(rest) => rest.to_string(),
+                None => flag,
+            },
+            Transform::Replace { from, to } => flag.replace(from.as_str(), to),
+        }
+    }
+}
+
+impl FlagFormat {
+    /// Resolves overrides against the ctfapi defaults. Config transforms run after the ctfapi's.
+    pub fn resolve(&self, ctf_api: &CTFApi) -> Result<(Vec<Regex>, Vec<Transform>), regex::Error> {
+        let mut regexes = if self.regex.is_empty() {
+            ctf_api.flag_regex.clone()
+        } else {
+            self.regex
+                .iter()
+                .map(|re| Regex::new(re))
+                .collect::<Result<_, _>>()?
+        };
+        for re in &self.extra_regex {
+            regexes.push(Regex::new(re)?);
+        }
+        let transforms = ctf_api
+            .transforms
+            .iter()
+            .chain(self.transforms.iter())
+            .cloned()
+            .collect();
+        Ok((regexes, transforms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::FlagMatcher;
+
+    fn noop_api() -> CTFApi {
+        crate::ctfapi::choose(Some("noop".into()), &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn transforms() {
+        let apply = |transform: Transform, flag: &str| transform.apply(flag.to_string());
+        assert_eq!(apply(Transform::Trim, " FLAG{a} \t"), "FLAG{a}");
+        assert_eq!(apply(Transform::Uppercase, "flag{ä}"), "FLAG{Ä}");
+        assert_eq!(apply(Transform::Lowercase, "FLAG{A}"), "flag{a}");
+        // graphemes, not bytes or chars
+        assert_eq!(
+            apply(Transform::TruncateGraphemes(3), "ae\u{301}xyz"),
+            "ae\u{301}x"
+        );
+        assert_eq!(apply(Transform::TruncateGraphemes(10), "abc"), "abc");
+        let strip_prefix = Transform::StripPrefix("flag=".into());
+        assert_eq!(apply(strip_prefix.clone(), "flag=FLAG{a}"), "FLAG{a}");
+        assert_eq!(apply(strip_prefix, "FLAG{a}"), "FLAG{a}");
+        let strip_suffix = Transform::StripSuffix("\\n".into());
+        assert_eq!(apply(strip_suffix.clone(), "FLAG{a}\\n"), "FLAG{a}");
+        assert_eq!(apply(strip_suffix, "FLAG{a}"), "FLAG{a}");
+        let replace = Transform::Replace {
+            from: "-".into(),
+            to: "_".into(),
+        };
+        assert_eq!(apply(replace, "FLAG{a-b-c}"), "FLAG{a_b_c}");
+
+        let parsed: Vec<Transform> = serde_json::from_str(
+            r#"["trim", {"truncate_graphemes": 5}, {"replace": {"from": "a", "to": "b"}}]"#,
+        )
+        .unwrap();
+        assert_eq!(parsed[1], Transform::TruncateGraphemes(5));
+    }
+
+    #[test]
+    fn overrides_and_extends_the_ctfapi_format() {
+        let mut ctf_api = noop_api();
+        ctf_api.transforms = vec![Transform::Trim];
+        let default_regex = ctf_api.flag_regex[0].as_str().to_string();
+        let resolve = |format: &FlagFormat| {
+            let (regexes, transforms) = format.resolve(&ctf_api).unwrap();
+            let regexes = regexes
+                .iter()
+                .map(|re| re.as_str().to_string())
+                .collect::<Vec<_>>();
+            (regexes, transforms)
+        };
+
+        let (regexes, transforms) = resolve(&FlagFormat::default());
+        assert_eq!(regexes, [default_regex.as_str()]);
+        assert_eq!(transforms, [Transform::Trim]);
+
+        let extended = FlagFormat {
+            extra_regex: vec!["ALT_[0-9]+".into()],
+            transforms: vec![Transform::Uppercase],
+            ..Default::default()
+        };
+        let (regexes, transforms) = resolve(&extended);
+        assert_eq!(regexes, [default_regex, "ALT_[0-9]+".to_string()]);
+        // the ctfapi's transforms run first
+        assert_eq!(transforms, [Transform::Trim, Transform::Uppercase]);
+
+        let overridden = FlagFormat {
+            regex: vec!["OWN_[a-z]+".into()],
+            extra_regex: vec!["ALT_[0-9]+".into()],
+            ..Default::default()
+        };
+        let (regexes, _) = resolve(&overridden);
+        assert_eq!(regexes, ["OWN_[a-z]+", "ALT_[0-9]+"]);
+
+        let invalid = FlagFormat {
+            extra_regex: vec!["FLAG{(".into()],
+            ..Default::default()
+        };
+        assert!(invalid.resolve(&ctf_api).is_err());
+    }
+
+    #[test]
+    fn regexes_that_cant_be_combined() {
+        let format = FlagFormat {
+            regex: vec!["(?P<id>A+)".into()],
+            extra_regex: vec!["(?P<id>B+)".into()],
+            ..Default::default()
+        };
+        let (regexes, transforms) = format.resolve(&noop_api()).unwrap();
+        assert!(FlagMatcher::new(&regexes[..1], transforms.clone(), &[]).is_ok());
+        assert!(FlagMatcher::new(&regexes, transforms, &[]).is_err());
+    }
+}
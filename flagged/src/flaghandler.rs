@@ -1,4 +1,5 @@
 use crate::events::SessionRunHandle;
+use crate::metrics::metrics;
 use crate::{ctfapi::Flag, submitter::FlagBatcher};
 use bloom::{BloomFilter, ASMS};
 use std::sync::Arc;
@@ -28,22 +29,26 @@ impl FlagHandler {
     pub async fn submit(&mut self, flag: &str, run_handle: Arc<Mutex<SessionRunHandle>>) -> bool {
         // TODO: remove this for perf and also to prevent spam?
         let is_unique = !self.seen.contains(&flag);
-        run_handle
-            .lock()
-            .await
-            .flag_match(flag.to_string(), is_unique);
+        let (target, interval, round) = {
+            let mut handle = run_handle.lock().await;
+            handle.flag_match(flag.to_string(), is_unique);
+            (handle.key().to_string(), handle.interval(), handle.round())
+        };
+        metrics().flags_matched.inc(&[&target]);
         if !is_unique {
             return false;
         }
+        metrics().flags_unique.inc(&[&target]);
 
         run_handle.lock().await.flag_pending(flag.to_string());
-        self.submit_unique(Flag::new(flag, &run_handle)).await;
+        self.submit_unique(Flag::new(flag, &target, &run_handle).captured_in(interval, round))
+            .await;
 
         self.seen.insert(&flag);
         self.uniques += 1;
 
         if self.uniques > 1 && self.uniques.is_power_of_two() {
-            println!(
+            tracing::info!(
                 "STAT: {} unique flags ({:.04}% of expected bloom size)",
                 self.uniques,
                 (self.uniques as f64 / FLAG_HISTORY_LIMIT as f64) * 100.0
@@ -54,10 +59,18 @@ impl FlagHandler {
     }
 
     async fn submit_unique(&mut self, flag: Flag) {
-        println!("UNIQ: {}", flag);
+        match flag.round {
+            Some(round) => tracing::info!(round, "UNIQ: {}", flag),
+            None => tracing::info!("UNIQ: {}", flag),
+        }
         self.flag_batcher.submit(flag).await;
     }
 
+    /// Number of flags waiting for submission
+    pub fn queue_depth(&self) -> usize {
+        self.flag_batcher.queue_depth()
+    }
+
     pub async fn flush(&mut self) {
         self.flag_batcher.flush().await;
     }
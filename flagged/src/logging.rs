@@ -0,0 +1,237 @@
+//! Leveled, structured logs on stderr and optionally in a (rotated) log file.
+//!
+//! Runs attach `target`, `run` and `interval` fields to everything logged while they're active.
+
+use crate::color::Color;
+use crate::ctfapi::VerdictClass;
+use clap::Args;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::field::{Field, Visit};
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::fmt::{FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[derive(Args, Debug)]
+pub struct LogOpts {
+    /// Log more: -v for debug, -vv for trace messages
+    #[clap(short = 'v', long = "verbose", parse(from_occurrences))]
+    verbose: u64,
+    /// Per-module log levels, e.g. 'info,flagged::submitter=debug'. Overrides -v and --debug
+    #[clap(long = "log-filter", env = "FLAGGED_LOG")]
+    filter: Option<String>,
+    /// Log format: text or json
+    #[clap(long = "log-format", default_value = "text")]
+    format: LogFormat,
+    /// Also write logs to this file
+    #[clap(long = "log-file")]
+    file: Option<PathBuf>,
+    /// Start a new log file never, hourly or daily. Rotated files are suffixed with the date
+    #[clap(long = "log-rotation", default_value = "never")]
+    rotation: LogRotation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {:?}, expected text or json", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LogRotation(Rotation);
+
+impl FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(LogRotation(Rotation::NEVER)),
+            "hourly" => Ok(LogRotation(Rotation::HOURLY)),
+            "daily" => Ok(LogRotation(Rotation::DAILY)),
+            _ => Err(format!(
+                "unknown log rotation {:?}, expected never, hourly or daily",
+                s
+            )),
+        }
+    }
+}
+
+/// Dependencies only get to warn, flagged itself logs at the level picked by -v and --debug
+fn directives(verbose: u64, debug: bool, filter: Option<&str>) -> String {
+    if let Some(filter) = filter {
+        return filter.to_string();
+    }
+    let level = match (verbose, debug) {
+        (0, false) => "info",
+        (0, true) | (1, _) => "debug",
+        _ => "trace",
+    };
+    format!("warn,flagged={}", level)
+}
+
+/// Colors the message of events with a verdict `class` field, like `run` colors verdicts. Other fields are left
+/// to the default formatter, which escapes control characters in messages.
+#[derive(Default)]
+struct VerdictFields(DefaultFields);
+
+#[derive(Default)]
+struct ClassVisitor(Option<VerdictClass>);
+
+impl Visit for ClassVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "class" {
+            self.0 = VerdictClass::ALL
+                .iter()
+                .copied()
+                .find(|class| class.as_str() == value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+impl<'writer> FormatFields<'writer> for VerdictFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        let mut class = ClassVisitor::default();
+        fields.record(&mut class);
+        let color = match class.0 {
+            Some(class) if writer.has_ansi_escapes() => Color::of_verdict(class),
+            _ => return self.0.format_fields(writer, fields),
+        };
+        // the message comes first, so this colors it until the first field name resets the style
+        write!(writer, "\x1b[{}m", color.code())?;
+        self.0.format_fields(writer.by_ref(), fields)?;
+        write!(writer, "\x1b[0m")
+    }
+}
+
+fn layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.compact().fmt_fields(VerdictFields::default()).boxed(),
+        // fields stay nested, so a `target` field doesn't clash with the module path
+        LogFormat::Json => layer.json().with_span_list(false).boxed(),
+    }
+}
+
+/// Installs the global logger. The returned guard flushes the log file when dropped.
+pub fn init(opts: &LogOpts, debug: bool) -> Result<Option<WorkerGuard>, String> {
+    let directives = directives(opts.verbose, debug, opts.filter.as_deref());
+    let filter = EnvFilter::try_new(&directives)
+        .map_err(|err| format!("invalid log filter {:?}: {}", directives, err))?;
+    let ansi = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+
+    let mut layers = vec![layer(opts.format, std::io::stderr, ansi)];
+    let mut guard = None;
+    if let Some(path) = &opts.file {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("invalid log file {:?}", path))?;
+        let dir = path.parent().unwrap_or_else(|| "".as_ref());
+        let appender = RollingFileAppender::builder()
+            .rotation(opts.rotation.0.clone())
+            .filename_prefix(file_name)
+            .build(dir)
+            .map_err(|err| format!("failed to open log file {:?}: {}", path, err))?;
+        let (writer, worker_guard) = tracing_appender::non_blocking(appender);
+        layers.push(layer(opts.format, writer, false));
+        guard = Some(worker_guard);
+    }
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .try_init()
+        .map_err(|err| format!("failed to set up logging: {}", err))?;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_maps_to_levels() {
+        assert_eq!(directives(0, false, None), "warn,flagged=info");
+        assert_eq!(directives(0, true, None), "warn,flagged=debug");
+        assert_eq!(directives(1, false, None), "warn,flagged=debug");
+        assert_eq!(directives(2, true, None), "warn,flagged=trace");
+        assert_eq!(
+            directives(2, true, Some("flagged::submitter=debug")),
+            "flagged::submitter=debug"
+        );
+        assert!("yaml".parse::<LogFormat>().is_err());
+        assert!("weekly".parse::<LogRotation>().is_err());
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_verdict(ansi: bool) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(layer(
+            LogFormat::Text,
+            move || writer.clone(),
+            ansi,
+        ));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(class = "accepted", "{} -> {}", "FLAG{a}", "OK");
+        });
+        let out = buffer.0.lock().unwrap().clone();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn colors_verdicts() {
+        assert!(log_verdict(true).contains("\x1b[32mFLAG{a} -> OK"));
+        let plain = log_verdict(false);
+        assert!(
+            plain.contains("FLAG{a} -> OK class=\"accepted\""),
+            "{}",
+            plain
+        );
+        assert!(!plain.contains('\x1b'));
+    }
+}
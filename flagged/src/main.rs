@@ -4,48 +4,100 @@
 // - save interval index to disk => allow fair restarts?
 // - submit flags mode -> read from stdin
 
-use clap::Parser;
-use futures::stream::{FuturesUnordered, StreamExt};
+use clap::{Parser, Subcommand};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
 
-use std::fs::File;
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
+mod color;
 mod config;
+mod configfile;
 mod ctfapi;
+mod eventlog;
 mod events;
+mod flagformat;
 mod flaghandler;
+mod logging;
+mod metrics;
+mod mockserver;
 mod proc;
+mod quickrun;
+mod runner;
+mod scanner;
 mod submitter;
+mod targetfilter;
+mod targetgen;
+mod template;
+mod ticks;
+#[cfg(feature = "tui")]
+mod tui;
 
-use config::{Config, Target};
-use submitter::FlagBatcher;
+#[cfg(test)]
+mod tests;
 
-const PRIMARY_KEY: &str = "IP";
+use config::Config;
+use submitter::FlagBatcher;
 
 #[derive(Parser, Debug)]
 #[clap(name = "flagged - KISS Exploit-Thrower mit Niveau")]
 struct Opts {
-    /// Config file path.
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// Config file path. JSON, TOML (.toml) and YAML (.yaml/.yml) are supported. Falls back to attacc.toml or attacc.yaml
     #[clap(short = 'c', long = "config", default_value = "attacc.json")]
     config: String,
+    /// Apply a named profile from the config's "profiles" table, e.g. local or game
+    #[clap(long = "profile", env = "FLAGGED_PROFILE")]
+    profile: Option<String>,
     /// Report exploit status to redis. The URL format is redis://[:<passwd>@]<hostname>[:port][/<db>]
     #[clap(long = "stats-uri")]
     stats_uri: Option<String>,
+    /// Also publish events to redis://..., redis-stream://...[?key=events&maxlen=N], jsonl:PATH, unix:PATH or stdout. Can be passed multiple times.
+    /// Paths are relative to the current directory, not the exploit folder
+    #[clap(long = "events")]
+    events: Vec<events::SinkSpec>,
+    /// Serve Prometheus metrics on http://<addr>/metrics, e.g. 0.0.0.0:9797
+    #[clap(long = "metrics-addr")]
+    metrics_addr: Option<String>,
     /// Working directory. If omitted, the current working directory will be used
     path: Option<String>,
     /// Choose flag submission backend and flag regex. Only neccesary if flagged was compiled with multiple backends
     #[clap(long = "ctf-api")]
     ctf_api: Option<String>,
+    /// Override the ctfapi's flag regex. Can be passed multiple times
+    #[clap(long = "flag-regex")]
+    flag_regex: Vec<String>,
+    /// Match this regex in addition to the ctfapi's flag regex. Can be passed multiple times
+    #[clap(long = "extra-flag-regex")]
+    extra_flag_regex: Vec<String>,
+    /// Only run against targets matching this filter, e.g. 'IP in 10.32.0.0/16'. Can be passed multiple times
+    #[clap(long = "include")]
+    include: Vec<targetfilter::Filter>,
+    /// Skip targets matching this filter, e.g. 'NAME == NOP' or 'NAME =~ ^team1$'. Can be passed multiple times
+    #[clap(long = "exclude")]
+    exclude: Vec<targetfilter::Filter>,
     /// Team token might be required by submission backend
     #[clap(long = "token")]
     team_token: Option<String>,
+    /// Override the submission backend's server address (or URL for HTTP-based backends)
+    #[clap(long = "submit-addr")]
+    submit_addr: Option<String>,
 
-    /// Debug mode: implies --concurrency=1 --stdout --stderr
+    /// Debug mode: implies --concurrency=1 --stdout --stderr and debug logs
     #[clap(short = 'd', long = "debug")]
     debug: bool,
+    /// Run exploits and extract flags as usual, but record flags to --dry-run-output instead of submitting them
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+    /// File the flags of a dry run are appended to, as JSON lines. Relative to the working directory
+    #[clap(long = "dry-run-output", default_value = "dry-run-flags.jsonl")]
+    dry_run_output: String,
+    /// Show a live dashboard of all targets instead of scrolling output
+    #[cfg(feature = "tui")]
+    #[clap(long = "tui")]
+    tui: bool,
     /// Print stdout of exploits
     #[clap(long = "stdout")]
     stdout: bool,
@@ -66,21 +118,105 @@ struct Opts {
     /// Dump configuration and exit
     #[clap(long = "dump-config")]
     dump_config: bool,
-    // /// A level of verbosity, and can be used multiple times
-    // #[clap(short = "v", long = "verbose", parse(from_occurrences))]
-    // verbose: i32,
+    #[clap(flatten)]
+    log: logging::LogOpts,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Emulate a gameserver's flag submission endpoint for local testing
+    MockServer(mockserver::MockServerOpts),
+    /// Run the exploit against selected targets, print a summary and exit
+    Run(quickrun::RunOpts),
+    /// Validate the config and report every problem found
+    Check,
+    /// Summarize or replay recorded events
+    Events(eventlog::EventsOpts),
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut opts: Opts = Opts::parse();
+    let log_guard = logging::init(&opts.log, opts.debug)?;
+
+    match opts.command.take() {
+        Some(Command::MockServer(mock_opts)) => mockserver::run(mock_opts).await,
+        Some(Command::Run(run_opts)) => {
+            opts.stdout = true;
+            opts.stderr = true;
+            let (events_tx, events_rx) = mpsc::unbounded_channel();
+            if let Some(runner) = setup(opts, vec![events::Publisher::Channel(events_tx)])? {
+                let success = quickrun::run(runner, events_rx, run_opts).await?;
+                // `exit` skips destructors, so flush the log file first
+                drop(log_guard);
+                std::process::exit(if success { 0 } else { 1 });
+            }
+            Ok(())
+        }
+        Some(Command::Events(events_opts)) => eventlog::run(events_opts).await,
+        Some(Command::Check) => {
+            opts.dump_config = true;
+            setup(opts, Vec::new())?;
+            println!("No problems found.");
+            Ok(())
+        }
+        #[cfg(feature = "tui")]
+        None if opts.tui => {
+            let (events_tx, events_rx) = mpsc::unbounded_channel();
+            if let Some(runner) = setup(opts, vec![events::Publisher::Channel(events_tx)])? {
+                // after setup, so config errors still end up on the terminal
+                let capture = tui::capture_output()?;
+                tui::run(runner, events_rx, capture).await?;
+            }
+            Ok(())
+        }
+        None => {
+            if let Some(mut runner) = setup(opts, Vec::new())? {
+                let (stop_tx, stop_rx) = watch::channel(false);
+                let (quit_tx, quit_rx) = oneshot::channel();
+                tokio::spawn(async move {
+                    // the first Ctrl-C lets the current interval finish, the second one quits right away
+                    if tokio::signal::ctrl_c().await.is_ok() {
+                        tracing::warn!(
+                            "Stopping after the current interval, press Ctrl-C again to quit now"
+                        );
+                        let _ = stop_tx.send(true);
+                        let _ = tokio::signal::ctrl_c().await;
+                        let _ = quit_tx.send(());
+                    }
+                });
+                tokio::select! {
+                    _ = runner.run(stop_rx) => {}
+                    Ok(()) = quit_rx => {
+                        drop(log_guard);
+                        std::process::exit(130);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
 
+/// Loads the config and wires up everything needed to run exploits.
+/// Returns `None` if there is nothing left to do, e.g. for --dump-config.
+fn setup(
+    mut opts: Opts,
+    mut publishers: Vec<events::Publisher>,
+) -> Result<Option<runner::Runner>, Box<dyn std::error::Error>> {
     let folder = opts.path.unwrap_or_else(|| String::from("."));
-    let mut config_path = Path::new(&folder).to_path_buf();
-    config_path.push(&opts.config);
+    let mut config_path = Path::new(&folder).join(&opts.config);
+    if !config_path.exists() && opts.config == configfile::DEFAULT_NAMES[0] {
+        if let Some(path) = configfile::DEFAULT_NAMES
+            .iter()
+            .map(|name| Path::new(&folder).join(name))
+            .find(|path| path.exists())
+        {
+            config_path = path;
+        }
+    }
 
-    let config_file = File::open(config_path).expect("failed to open config");
-    let mut config: Config = serde_json::from_reader(config_file).expect("failed to parse json");
+    let mut config = Config::load(&config_path, opts.profile.as_deref())?;
 
     if opts.debug {
         opts.concurrency = opts.concurrency.or(Some(1));
@@ -91,110 +227,141 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     config.concurrency = opts.concurrency.unwrap_or(config.concurrency);
     config.interval = opts.interval.unwrap_or(config.interval);
     config.timeout = opts.timeout.unwrap_or(config.timeout);
+    if !opts.flag_regex.is_empty() {
+        config.flag_format.regex = opts.flag_regex;
+    }
+    config.flag_format.extra_regex.extend(opts.extra_flag_regex);
+    config.include.extend(opts.include);
+    config.exclude.extend(opts.exclude);
 
-    let ctf_api = ctfapi::choose(opts.ctf_api);
-    let flag_regex = ctf_api.flag_regex.clone();
+    let (targets, mut problems) = match config.check(&folder) {
+        Ok(targets) => (targets, Vec::new()),
+        Err(problems) => (Vec::new(), problems),
+    };
+    let ctf_api_settings = ctfapi::Settings {
+        addr: opts.submit_addr.or_else(|| config.submit_addr.clone()),
+        token: opts.team_token.or_else(|| config.token.clone()),
+    };
+    let ctf_api = ctfapi::choose(
+        opts.ctf_api.or_else(|| config.ctf_api.clone()),
+        &ctf_api_settings,
+    );
+    // the flag format and submission policy depend on the backend
+    let dry_run = opts.dry_run;
+    let backend = ctf_api
+        .map_err(|err| config::Problem::new("ctf_api", err))
+        .and_then(|ctf_api| {
+            // a dry run never talks to the gameserver
+            if !dry_run {
+                ctf_api
+                    .submitter
+                    .check()
+                    .map_err(|err| config::Problem::new("submit_addr", err.to_string()))?;
+            }
+            let flag_matcher = config.flag_matcher(&ctf_api)?;
+            let submission_policy = config
+                .submission_policy(&ctf_api)
+                .map_err(|err| config::Problem::new("submission", err))?;
+            Ok((ctf_api, flag_matcher, submission_policy))
+        });
+    let (mut ctf_api, flag_matcher, submission_policy) = match backend {
+        Ok(backend) if problems.is_empty() => backend,
+        result => {
+            problems.extend(result.err());
+            return Err(config::ConfigError {
+                path: config_path,
+                problems,
+            }
+            .into());
+        }
+    };
+    let flag_matcher = Arc::new(flag_matcher);
 
     if opts.debug || opts.dump_config {
         config.explain(&ctf_api);
     }
+    // nothing has been opened or published yet
+    if opts.dump_config {
+        config.dump_targets();
+        return Ok(None);
+    }
 
-    let redis_client = opts
-        .stats_uri
-        .map(|uri| redis::Client::open(uri).expect("invalid redis uri"));
+    if let Some(uri) = opts.stats_uri {
+        publishers.push(events::Publisher::Redis(
+            redis::Client::open(uri).expect("invalid redis uri"),
+        ));
+    }
+    for spec in &opts.events {
+        publishers.push(events::Publisher::open(spec)?);
+    }
 
     let path = std::fs::canonicalize(&folder).unwrap();
     let hostname = hostname::get().unwrap().into_string().unwrap();
-    let mut events_session = events::Session::open(
-        redis_client,
+    let events_session = events::Session::open(
+        publishers,
         events::SessionAnnouncement {
-            config: config.clone(),
+            config: serde_json::to_value(&config)?,
             hostname,
             path: format!("{:?}", path),
         },
     );
 
-    if opts.dump_config {
-        return Ok(());
+    if let Some(addr) = opts.metrics_addr {
+        let listener = std::net::TcpListener::bind(&addr)?;
+        listener.set_nonblocking(true)?;
+        tracing::info!(
+            "Serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+        tokio::spawn(metrics::serve(tokio::net::TcpListener::from_std(listener)?));
+    }
+
+    if opts.dry_run {
+        let output = Path::new(&folder).join(&opts.dry_run_output);
+        tracing::warn!("Dry run! Recording flags to {:?}", output);
+        ctf_api.submitter = Box::new(ctfapi::DryRunSubmitter::create(&output)?);
+        ctf_api.test_flag = None;
     }
 
     if let Some(test_flag) = ctf_api.test_flag.as_ref() {
-        println!("Submitting test flag {:?}...", test_flag);
+        tracing::info!("Submitting test flag {:?}...", test_flag);
         let fake_run_handle = events::SessionRunHandle::noop();
-        let flag = ctfapi::Flag::new(&test_flag, &Arc::new(Mutex::new(fake_run_handle)));
+        let flag = ctfapi::Flag::new(test_flag, "", &Arc::new(Mutex::new(fake_run_handle)));
+        let batch = [flag];
         ctf_api
             .submitter
-            .submit_batch(&[flag])
-            .expect("failed to submit test flag")
+            .submit_batch(&batch)
+            .map_err(|err| format!("failed to submit test flag: {}", err))?;
+        if batch[0].verdict().is_none() {
+            return Err("test flag did not receive a verdict".into());
+        }
     }
 
-    let flag_batcher = FlagBatcher::start(ctf_api.submitter);
+    let flag_batcher = FlagBatcher::start(
+        ctf_api.submitter,
+        submission_policy,
+        config.flag_lifetime(),
+        events_session.handle(),
+    );
     let flag_handler = Arc::new(Mutex::new(flaghandler::FlagHandler::new(flag_batcher)));
 
     let process_config = proc::ProcessConfig {
-        flag_regex,
+        flag_matcher,
+        max_line_length: config.max_line_length,
         flag_handler: flag_handler.clone(),
         print_stdout: opts.stdout,
         print_stderr: opts.stderr,
         timeout: Duration::from_secs_f64(config.timeout),
     };
 
-    let mut jobs = FuturesUnordered::new();
-    // NOTE: `active` vastly over-estimates actives jobs for well-behaving exploits
-    let mut active = 0;
-
-    let targets = config
-        .targets
-        .iter()
-        .map(|target| {
-            Arc::new(Target::new(
-                PRIMARY_KEY,
-                &config.command,
-                target,
-                folder.clone(),
-            ))
-        })
-        .collect::<Vec<_>>();
-
-    loop {
-        println!("Starting interval...");
-        events_session.start_interval();
-        let started_at = Instant::now();
-        for target in &targets {
-            if active == config.concurrency {
-                jobs.next().await;
-                active -= 1;
-            }
-            let run_handle = events_session.run_handle(target);
-            jobs.push(process_config.spawn(target.clone(), run_handle));
-            active += 1;
-        }
-
-        // drain active jobs
-        while active != 0 {
-            jobs.next().await;
-            active -= 1;
-        }
-
-        {
-            flag_handler.lock().await.flush().await;
-        }
-
-        events_session.end_interval();
-
-        let elapsed = started_at.elapsed();
-        if elapsed.as_secs_f64() >= config.interval {
-            println!(
-                "Late! Missed interval deadline by {:?}",
-                elapsed - Duration::from_secs_f64(config.interval)
-            );
-        } else {
-            println!(
-                "Done! Snoozing for {:?}",
-                Duration::from_secs_f64(config.interval) - elapsed
-            );
-            let deadline = started_at + Duration::from_secs_f64(config.interval);
-            tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await;
-        }
-    }
+    Ok(Some(runner::Runner {
+        interval: Duration::from_secs_f64(config.interval),
+        concurrency: config.concurrency,
+        targets,
+        process_config,
+        flag_handler,
+        events_session,
+        clock: config.tick.clone().map(ticks::Clock::new),
+        round: None,
+    }))
 }
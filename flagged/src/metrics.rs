@@ -0,0 +1,369 @@
+//! Prometheus metrics, served on `/metrics` with `--metrics-addr`.
+//!
+//! Metrics are always recorded. Without `--metrics-addr` nobody gets to see them.
+
+use crate::ctfapi::VerdictClass;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: usize = 8192;
+const LATENCY_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// A counter or gauge with a fixed set of label names
+pub struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, f64>>,
+}
+
+impl Family {
+    const fn new(
+        kind: &'static str,
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Family {
+            name,
+            help,
+            kind,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn add(&self, labels: &[&str], value: f64) {
+        debug_assert_eq!(labels.len(), self.labels.len(), "{}", self.name);
+        let key = labels.iter().map(|label| label.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_default() += value;
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.add(labels, 1.0);
+    }
+
+    pub fn set(&self, labels: &[&str], value: f64) {
+        let key = labels.iter().map(|label| label.to_string()).collect();
+        self.values.lock().unwrap().insert(key, value);
+    }
+
+    pub fn get(&self, labels: &[&str]) -> f64 {
+        let key = labels
+            .iter()
+            .map(|label| label.to_string())
+            .collect::<Vec<_>>();
+        self.values
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind);
+        for (values, value) in self.values.lock().unwrap().iter() {
+            let labels = self.labels.iter().zip(values).collect::<Vec<_>>();
+            let _ = writeln!(out, "{}{} {}", self.name, format_labels(&labels), value);
+        }
+    }
+}
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    buckets: &'static [f64],
+    // per-bucket counts, sum, count
+    state: Mutex<(Vec<u64>, f64, u64)>,
+}
+
+impl Histogram {
+    const fn new(name: &'static str, help: &'static str, buckets: &'static [f64]) -> Self {
+        Histogram {
+            name,
+            help,
+            buckets,
+            state: Mutex::new((Vec::new(), 0.0, 0)),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        let (counts, sum, count) = &mut *state;
+        counts.resize(self.buckets.len(), 0);
+        for (bucket, counter) in self.buckets.iter().zip(counts.iter_mut()) {
+            if value <= *bucket {
+                *counter += 1;
+            }
+        }
+        *sum += value;
+        *count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let (counts, sum, count) = &*self.state.lock().unwrap();
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            let bucket_count = counts.get(idx).copied().unwrap_or(0);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                self.name, bucket, bucket_count
+            );
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", self.name, count);
+        let _ = writeln!(out, "{}_sum {}", self.name, sum);
+        let _ = writeln!(out, "{}_count {}", self.name, count);
+    }
+}
+
+fn format_labels(labels: &[(&&str, &String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", labels.join(","))
+}
+
+pub struct Metrics {
+    pub runs_started: Family,
+    pub run_timeouts: Family,
+    pub run_exits: Family,
+    pub flags_matched: Family,
+    pub flags_unique: Family,
+    pub flag_verdicts: Family,
+    pub flags_dropped: Family,
+    pub submission_latency: Histogram,
+    pub queue_depth: Family,
+    pub intervals: Family,
+    pub interval_lateness: Family,
+    pub rounds_skipped: Family,
+    pub events_dropped: Family,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            runs_started: Family::new(
+                "counter",
+                "flagged_runs_started_total",
+                "Exploit runs started",
+                &["target"],
+            ),
+            run_timeouts: Family::new(
+                "counter",
+                "flagged_run_timeouts_total",
+                "Exploit runs killed after the timeout",
+                &["target"],
+            ),
+            run_exits: Family::new(
+                "counter",
+                "flagged_run_exits_total",
+                "Exploit runs that exited, by exit code (\"signal\" if killed by a signal)",
+                &["target", "code"],
+            ),
+            flags_matched: Family::new(
+                "counter",
+                "flagged_flags_matched_total",
+                "Flags found in exploit output, including duplicates",
+                &["target"],
+            ),
+            flags_unique: Family::new(
+                "counter",
+                "flagged_flags_unique_total",
+                "Flags found for the first time",
+                &["target"],
+            ),
+            flag_verdicts: Family::new(
+                "counter",
+                "flagged_flag_verdicts_total",
+                "Verdicts by target and verdict class",
+                &["target", "class"],
+            ),
+            flags_dropped: Family::new(
+                "counter",
+                "flagged_flags_dropped_total",
+                "Flags given up without a verdict, because they expired or the submitter returned none",
+                &["target", "reason"],
+            ),
+            submission_latency: Histogram::new(
+                "flagged_submission_latency_seconds",
+                "Time spent submitting a batch of flags",
+                LATENCY_BUCKETS,
+            ),
+            queue_depth: Family::new(
+                "gauge",
+                "flagged_submission_queue_depth",
+                "Flags waiting for a verdict",
+                &[],
+            ),
+            intervals: Family::new(
+                "counter",
+                "flagged_intervals_total",
+                "Intervals started",
+                &[],
+            ),
+            interval_lateness: Family::new(
+                "gauge",
+                "flagged_interval_lateness_seconds",
+                "How far the last interval overran its deadline",
+                &[],
+            ),
+            rounds_skipped: Family::new(
+                "counter",
+                "flagged_rounds_skipped_total",
+                "Rounds skipped because an interval took too long",
+                &[],
+            ),
+            events_dropped: Family::new(
+                "counter",
+                "flagged_events_dropped_total",
+                "Events a sink missed because it couldn't keep up or was unreachable",
+                &["sink"],
+            ),
+        }
+    }
+
+    pub fn verdict(&self, target: &str, verdict: &str) {
+        self.flag_verdicts
+            .inc(&[target, VerdictClass::of(verdict).as_str()]);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in [
+            &self.runs_started,
+            &self.run_timeouts,
+            &self.run_exits,
+            &self.flags_matched,
+            &self.flags_unique,
+            &self.flag_verdicts,
+            &self.flags_dropped,
+        ] {
+            family.render(&mut out);
+        }
+        self.submission_latency.render(&mut out);
+        for family in [
+            &self.queue_depth,
+            &self.intervals,
+            &self.interval_lateness,
+            &self.rounds_skipped,
+            &self.events_dropped,
+        ] {
+            family.render(&mut out);
+        }
+        out
+    }
+}
+
+static METRICS: Metrics = Metrics::new();
+
+/// The process-wide registry
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+async fn handle(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let size = stream.read(&mut buf).await?;
+        if size == 0 || request.len() + size > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..size]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split(' ');
+    let (method, path) = (request_line.next(), request_line.next());
+    let path = path.map(|path| path.split('?').next().unwrap_or(path));
+
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics().render()),
+        (Some("GET"), _) => ("404 Not Found", "try /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+/// Serves `/metrics` until the process exits.
+pub async fn serve(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    let _ = tokio::time::timeout(REQUEST_TIMEOUT, handle(stream)).await;
+                });
+            }
+            Err(err) => {
+                tracing::warn!("metrics: failed to accept connection: {:?}", err);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition_format() {
+        let metrics = Metrics::new();
+        metrics.runs_started.inc(&["10.0.0.1"]);
+        metrics.runs_started.inc(&["10.0.0.1"]);
+        metrics.run_exits.inc(&["team \"1\"", "0"]);
+        metrics.verdict("10.0.0.1", "[OK] accepted");
+        metrics.verdict("10.0.0.1", "already submitted");
+        metrics.submission_latency.observe(0.07);
+        metrics.submission_latency.observe(3.0);
+        metrics.queue_depth.set(&[], 12.0);
+        assert_eq!(metrics.queue_depth.get(&[]), 12.0);
+        assert_eq!(metrics.runs_started.get(&["10.0.0.2"]), 0.0);
+
+        let out = metrics.render();
+        for expected in [
+            "# TYPE flagged_runs_started_total counter",
+            "flagged_runs_started_total{target=\"10.0.0.1\"} 2",
+            "flagged_run_exits_total{target=\"team \\\"1\\\"\",code=\"0\"} 1",
+            "flagged_flag_verdicts_total{target=\"10.0.0.1\",class=\"accepted\"} 1",
+            "flagged_flag_verdicts_total{target=\"10.0.0.1\",class=\"duplicate\"} 1",
+            "flagged_submission_latency_seconds_bucket{le=\"0.05\"} 0",
+            "flagged_submission_latency_seconds_bucket{le=\"0.1\"} 1",
+            "flagged_submission_latency_seconds_bucket{le=\"+Inf\"} 2",
+            "flagged_submission_latency_seconds_count 2",
+            "flagged_submission_queue_depth 12",
+        ] {
+            assert!(
+                out.lines().any(|line| line == expected),
+                "{}\n{}",
+                expected,
+                out
+            );
+        }
+    }
+}
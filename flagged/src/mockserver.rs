@@ -0,0 +1,329 @@
+//! Emulates the flag submission endpoints of the supported ctfapi backends for offline testing,
+//! like `test-submitter-server.py` but for every protocol.
+
+use clap::Args;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const DEFAULT_RESPONSES: &[(&str, &str)] = &[
+    ("VAL_", "[OK]"),
+    ("INV_", "[ERR] Invalid flag"),
+    ("EXP_", "[ERR] Expired"),
+    ("DUP_", "[ERR] Already submitted"),
+    ("NOP_", "[ERR] Can't submit flag from NOP team"),
+    ("OWN_", "[ERR] This is your own flag"),
+];
+const FALLBACK_RESPONSE: &str = "[ERR] Invalid format";
+
+#[derive(Args, Debug)]
+pub struct MockServerOpts {
+    /// Submission protocol to emulate: saarctf, enowars, mhackectf, faust, forcad or ructf
+    #[clap(long = "protocol", default_value = "saarctf")]
+    protocol: Protocol,
+    /// Address to listen on
+    #[clap(long = "listen", default_value = "127.0.0.1:31337")]
+    listen: String,
+    /// Respond with VERDICT to flags starting with PREFIX, either at the very start or right after
+    /// the flag format's prefix (e.g. `SAAR{`). Format: PREFIX=VERDICT. Can be passed multiple times
+    #[clap(long = "response")]
+    responses: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// One flag per line, one verdict per line (saarctf, enowars, mhackectf)
+    Lines,
+    /// Line protocol with welcome banners
+    Faust,
+    /// Line protocol with team token handshake
+    Forcad,
+    /// HTTP PUT of a JSON flag list
+    Ructf,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "saarctf" | "enowars" | "mhackectf" => Ok(Protocol::Lines),
+            "faust" => Ok(Protocol::Faust),
+            "forcad" => Ok(Protocol::Forcad),
+            "ructf" => Ok(Protocol::Ructf),
+            _ => Err(format!("unsupported protocol {:?}", s)),
+        }
+    }
+}
+
+pub struct Responses {
+    rules: Vec<(String, String)>,
+}
+
+impl Default for Responses {
+    fn default() -> Self {
+        Responses::new(Vec::new())
+    }
+}
+
+impl Responses {
+    /// Custom rules take precedence over the default `VAL_`/`INV_`/... table.
+    pub fn new(mut rules: Vec<(String, String)>) -> Self {
+        rules.extend(
+            DEFAULT_RESPONSES
+                .iter()
+                .map(|&(prefix, verdict)| (prefix.into(), verdict.into())),
+        );
+        Responses { rules }
+    }
+
+    pub fn verdict(&self, flag: &str) -> &str {
+        let inner = flag.find(['{', '_']).map(|i| &flag[i + 1..]);
+        for (prefix, verdict) in &self.rules {
+            if flag.starts_with(prefix.as_str())
+                || inner.is_some_and(|inner| inner.starts_with(prefix.as_str()))
+            {
+                return verdict;
+            }
+        }
+        FALLBACK_RESPONSE
+    }
+}
+
+pub struct MockServer {
+    protocol: Protocol,
+    responses: Responses,
+}
+
+impl MockServer {
+    pub fn new(protocol: Protocol, responses: Responses) -> Self {
+        MockServer {
+            protocol,
+            responses,
+        }
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let res = match server.protocol {
+                    Protocol::Ructf => server.handle_http(stream).await,
+                    _ => server.handle_lines(stream).await,
+                };
+                if let Err(err) = res {
+                    tracing::warn!("mockserver: {}: {:?}", peer, err);
+                }
+            });
+        }
+    }
+
+    async fn handle_lines(&self, stream: TcpStream) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        match self.protocol {
+            Protocol::Faust => {
+                writer
+                    .write_all(b"Flag submission server\nOne flag per line please!\n")
+                    .await?
+            }
+            Protocol::Forcad => {
+                writer
+                    .write_all(b"Welcome! Please, enter your team token:\n")
+                    .await?;
+                let token = lines.next_line().await?.unwrap_or_default();
+                println!("team token: {:?}", token);
+                writer
+                    .write_all(b"Now enter your flags, one in a line:\n")
+                    .await?;
+            }
+            _ => {}
+        }
+
+        while let Some(flag) = lines.next_line().await? {
+            let verdict = self.responses.verdict(flag.trim());
+            println!("{} -> {}", flag, verdict);
+            writer
+                .write_all(format!("{}\n", verdict).as_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_http(&self, stream: TcpStream) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                match &*name.trim().to_ascii_lowercase() {
+                    "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                    "x-team-token" => println!("team token: {:?}", value.trim()),
+                    _ => {}
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await?;
+
+        let (status, response) = if !request_line.starts_with("PUT /flags ") {
+            ("404 Not Found", json!({ "error": "not found" }))
+        } else {
+            match serde_json::from_slice::<Vec<String>>(&body) {
+                Ok(flags) => {
+                    let results = flags
+                        .iter()
+                        .map(|flag| {
+                            let verdict = self.responses.verdict(flag);
+                            println!("{} -> {}", flag, verdict);
+                            json!({
+                                "flag": flag,
+                                "status": verdict.starts_with("[OK]"),
+                                "msg": verdict,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    ("200 OK", json!(results))
+                }
+                Err(err) => ("400 Bad Request", json!({ "error": err.to_string() })),
+            }
+        };
+
+        let response = response.to_string();
+        writer
+            .write_all(
+                format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    response.len(),
+                    response
+                )
+                .as_bytes(),
+            )
+            .await
+    }
+}
+
+pub async fn run(opts: MockServerOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rules = Vec::new();
+    for response in &opts.responses {
+        let (prefix, verdict) = response
+            .split_once('=')
+            .ok_or_else(|| format!("invalid response {:?}, expected PREFIX=VERDICT", response))?;
+        rules.push((prefix.to_string(), verdict.to_string()));
+    }
+
+    let listener = TcpListener::bind(&opts.listen).await?;
+    println!(
+        "Emulating {:?} submission server on {}",
+        opts.protocol,
+        listener.local_addr()?
+    );
+    let server = Arc::new(MockServer::new(opts.protocol, Responses::new(rules)));
+    server.serve(listener).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ctfapi::{self, Flag, Settings};
+    use crate::events::SessionRunHandle;
+    use tokio::sync::Mutex;
+
+    const FLAGS: &[&str] = &["FLAG{VAL_1}", "FLAG{EXP_2}", "VAL_3", "garbage"];
+    const VERDICTS: &[&str] = &["[OK]", "[ERR] Expired", "[OK]", FALLBACK_RESPONSE];
+
+    async fn submit_to_mock(ctf_api: &str, protocol: Protocol) -> Vec<Option<String>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(MockServer::new(protocol, Responses::default()));
+        tokio::spawn(server.serve(listener));
+
+        let addr = match protocol {
+            Protocol::Ructf => format!("http://{}/flags", addr),
+            _ => addr.to_string(),
+        };
+        let settings = Settings {
+            addr: Some(addr),
+            token: Some("TOKEN".into()),
+        };
+        let ctf_api = ctfapi::choose(Some(ctf_api.into()), &settings).unwrap();
+        let run_handle = Arc::new(Mutex::new(SessionRunHandle::noop()));
+        let batch = FLAGS
+            .iter()
+            .map(|flag| Flag::new(flag, "", &run_handle))
+            .collect::<Vec<_>>();
+
+        tokio::task::spawn_blocking(move || {
+            ctf_api.submitter.submit_batch(&batch).unwrap();
+            batch.iter().map(Flag::verdict).collect()
+        })
+        .await
+        .unwrap()
+    }
+
+    fn expected() -> Vec<Option<String>> {
+        VERDICTS.iter().map(|v| Some(v.to_string())).collect()
+    }
+
+    #[test]
+    fn responses_match_after_format_prefix() {
+        let responses = Responses::new(vec![("SPECIAL".into(), "[OK] special".into())]);
+        assert_eq!(responses.verdict("SAAR{SPECIAL_x}"), "[OK] special");
+        assert_eq!(responses.verdict("FAUST_DUP_x"), "[ERR] Already submitted");
+        assert_eq!(responses.verdict("INV_xyz="), "[ERR] Invalid flag");
+        assert_eq!(responses.verdict("SAAR{xyz}"), FALLBACK_RESPONSE);
+    }
+
+    #[cfg(feature = "ctfapi-saarctf")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn saarctf() {
+        assert_eq!(submit_to_mock("saarctf", Protocol::Lines).await, expected());
+    }
+
+    #[cfg(feature = "ctfapi-enowars")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn enowars() {
+        assert_eq!(submit_to_mock("enowars", Protocol::Lines).await, expected());
+    }
+
+    #[cfg(feature = "ctfapi-mhackectf")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mhackectf() {
+        assert_eq!(
+            submit_to_mock("mhackectf", Protocol::Lines).await,
+            expected()
+        );
+    }
+
+    #[cfg(feature = "ctfapi-faust")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn faust() {
+        assert_eq!(submit_to_mock("faust", Protocol::Faust).await, expected());
+    }
+
+    #[cfg(feature = "ctfapi-forcad")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn forcad() {
+        assert_eq!(submit_to_mock("forcad", Protocol::Forcad).await, expected());
+    }
+
+    #[cfg(feature = "ctfapi-ructf")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ructf() {
+        assert_eq!(submit_to_mock("ructf", Protocol::Ructf).await, expected());
+    }
+}
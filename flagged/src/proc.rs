@@ -1,127 +1,290 @@
 use crate::config::Target;
+use crate::events::ResourceUsage;
 use crate::flaghandler::FlagHandler;
+use crate::metrics::metrics;
 
-use regex::bytes::Regex;
-use std::process::Stdio;
+use crate::scanner::{FlagMatcher, LineScanner};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStderr, ChildStdout};
 use tokio::sync::Mutex;
+use tokio::task::JoinError;
 use tokio::time;
+use tracing::Instrument;
+
+// Time granted to read the remaining output of killed processes
+const OUTPUT_GRACE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Blocks until the child exited, but leaves it a zombie: its pid can't be reused until it's reaped
+fn wait_exited(pid: u32) -> io::Result<()> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    loop {
+        let options = libc::WEXITED | libc::WNOWAIT;
+        if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, options) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Reaps an exited child with `wait4`, which also reports its resource usage
+fn reap(pid: u32) -> io::Result<(ExitStatus, ResourceUsage)> {
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut rusage) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+    // kilobytes on Linux, bytes on macOS
+    let max_rss_kb = if cfg!(target_os = "macos") {
+        rusage.ru_maxrss as u64 / 1024
+    } else {
+        rusage.ru_maxrss as u64
+    };
+    let usage = ResourceUsage {
+        user_time: seconds(rusage.ru_utime),
+        system_time: seconds(rusage.ru_stime),
+        max_rss_kb,
+    };
+    Ok((ExitStatus::from_raw(status), usage))
+}
+
+/// An exploit spawned with std's `Command`, so tokio's reaper never waits for it. It's reaped by
+/// `wait` only, and killed by pid until then.
+struct Process {
+    pid: u32,
+    /// Set once the process is reaped and its pid may belong to another process
+    reaped: Arc<std::sync::Mutex<bool>>,
+}
+
+impl Process {
+    /// Blocks until the process exited and reaps it, even if nobody is interested in its status anymore
+    fn wait(&self) -> io::Result<(ExitStatus, ResourceUsage)> {
+        wait_exited(self.pid)?;
+        let mut reaped = self.reaped.lock().unwrap();
+        *reaped = true;
+        reap(self.pid)
+    }
+
+    fn kill(&self) -> io::Result<()> {
+        let reaped = self.reaped.lock().unwrap();
+        if !*reaped && unsafe { libc::kill(self.pid as libc::pid_t, libc::SIGKILL) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+fn collect(
+    waited: Result<io::Result<(ExitStatus, ResourceUsage)>, JoinError>,
+) -> Option<(ExitStatus, ResourceUsage)> {
+    match waited.map_err(io::Error::from).and_then(|waited| waited) {
+        Ok(waited) => Some(waited),
+        Err(err) => {
+            tracing::error!("failed to wait for process: {:?}", err);
+            None
+        }
+    }
+}
 
 pub struct ProcessConfig {
     pub print_stdout: bool,
     pub print_stderr: bool,
-    pub flag_regex: Regex,
+    pub flag_matcher: Arc<FlagMatcher>,
+    pub max_line_length: usize,
     pub flag_handler: Arc<Mutex<FlagHandler>>,
     pub timeout: Duration,
 }
 
 impl ProcessConfig {
+    /// Runs the exploit against `target`. Everything logged meanwhile is tagged with the target and run.
     pub async fn spawn(
+        &self,
+        target: Arc<Target>,
+        run_handle: crate::events::SessionRunHandle,
+        round: Option<u64>,
+    ) {
+        let span = tracing::info_span!(
+            "run",
+            target = %target.key,
+            run = %run_handle.run_id(),
+            interval = run_handle.interval()
+        );
+        self.run(target, run_handle, round).instrument(span).await
+    }
+
+    async fn run(
         &self,
         target: Arc<Target>,
         mut run_handle: crate::events::SessionRunHandle,
+        round: Option<u64>,
     ) {
         let mut cmd = target.prepare();
+        if let Some(round) = round {
+            cmd.env("ROUND", round.to_string());
+        }
 
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
         let mut child = match cmd.spawn() {
             Ok(child) => child,
-            Err(err) => return eprintln!("cmd.spawn failed: {:?}", err),
+            Err(err) => {
+                tracing::error!("failed to spawn exploit: {:?}", err);
+                return run_handle.spawn_failed(err.to_string());
+            }
         };
-
-        run_handle.start();
-        let run_handle = Arc::new(Mutex::new(run_handle));
+        let started_at = Instant::now();
+        let process = Arc::new(Process {
+            pid: child.id(),
+            reaped: Default::default(),
+        });
+        let waiting = process.clone();
+        let mut exited = tokio::task::spawn_blocking(move || waiting.wait());
 
         let stdout = child
             .stdout
             .take()
             .expect("child did not have a handle to stdout");
-
         let stderr = child
             .stderr
             .take()
             .expect("child did not have a handle to stderr");
+        let (mut stdout, mut stderr) = match ChildStdout::from_std(stdout)
+            .and_then(|stdout| Ok((stdout, ChildStderr::from_std(stderr)?)))
+        {
+            Ok(pipes) => pipes,
+            Err(err) => {
+                tracing::error!("failed to read exploit output: {:?}", err);
+                let _ = process.kill();
+                return run_handle.spawn_failed(err.to_string());
+            }
+        };
+
+        run_handle.start();
+        metrics().runs_started.inc(&[&target.key]);
+        let run_handle = Arc::new(Mutex::new(run_handle));
 
-        let flag_regex = self.flag_regex.clone();
-        let mut stdout_reader = BufReader::new(stdout);
-        let stdout_target = target.clone();
+        let mut stdout_scanner =
+            LineScanner::new(Some(self.flag_matcher.clone()), self.max_line_length);
         let print_stdout = self.print_stdout;
         let flag_handler = self.flag_handler.clone();
         let stdout_run_handle = run_handle.clone();
 
-        tokio::spawn(async move {
-            let pkey = &*stdout_target.key;
-            let mut buf = Vec::new();
-            while let Ok(size) = stdout_reader.read_until(b'\n', &mut buf).await {
-                if size == 0 {
-                    break;
-                }
-                buf.truncate(size);
-                if buf.ends_with(b"\n") {
-                    buf.truncate(buf.len() - 1);
-                }
-                let line = String::from_utf8_lossy(&buf).to_string();
-                if print_stdout {
-                    println!("{} | {}", pkey, line);
-                }
-                stdout_run_handle.lock().await.stdout_line(line.clone());
-                if flag_regex.is_match(&buf) {
-                    let flags = flag_regex
-                        .find_iter(line.as_bytes())
-                        .map(|flag| String::from_utf8_lossy(flag.as_bytes()))
-                        .collect::<Vec<_>>();
-                    let mut handler = flag_handler.lock().await;
-                    for flag in &flags {
-                        handler.submit(flag, stdout_run_handle.clone()).await;
+        let deadline = time::Instant::now() + self.timeout;
+
+        let mut stdout_task = tokio::spawn(
+            async move {
+                let mut buf = [0; 8192];
+                loop {
+                    let (segments, eof) = match stdout.read(&mut buf).await {
+                        Ok(0) | Err(_) => (stdout_scanner.finish().into_iter().collect(), true),
+                        Ok(size) => (stdout_scanner.feed(&buf[..size]), false),
+                    };
+                    for segment in segments {
+                        if print_stdout {
+                            tracing::info!("stdout | {}", segment.line);
+                        }
+                        stdout_run_handle.lock().await.stdout_line(segment.line);
+                        if !segment.flags.is_empty() {
+                            let mut handler = flag_handler.lock().await;
+                            for flag in &segment.flags {
+                                handler.submit(flag, stdout_run_handle.clone()).await;
+                            }
+                        }
+                    }
+                    if eof {
+                        break;
                     }
                 }
-                buf.clear();
             }
-        });
+            .instrument(tracing::Span::current()),
+        );
 
-        let mut stderr_reader = BufReader::new(stderr);
-        let stderr_target = target.clone();
+        let mut stderr_scanner = LineScanner::new(None, self.max_line_length);
         let print_stderr = self.print_stderr;
         let stderr_run_handle = run_handle.clone();
-        tokio::spawn(async move {
-            let pkey = &*stderr_target.key;
-            let mut buf = Vec::new();
-            while let Ok(size) = stderr_reader.read_until(b'\n', &mut buf).await {
-                if size == 0 {
-                    break;
-                }
-                buf.truncate(size);
-                if buf.ends_with(b"\n") {
-                    buf.truncate(buf.len() - 1);
-                }
-                let line = String::from_utf8_lossy(&buf).to_string();
-                if print_stderr {
-                    eprintln!("{} | {}", pkey, line);
+        let mut stderr_task = tokio::spawn(
+            async move {
+                let mut buf = [0; 8192];
+                loop {
+                    let (segments, eof) = match stderr.read(&mut buf).await {
+                        Ok(0) | Err(_) => (stderr_scanner.finish().into_iter().collect(), true),
+                        Ok(size) => (stderr_scanner.feed(&buf[..size]), false),
+                    };
+                    for segment in segments {
+                        if print_stderr {
+                            tracing::info!("stderr | {}", segment.line);
+                        }
+                        stderr_run_handle.lock().await.stderr_line(segment.line);
+                    }
+                    if eof {
+                        break;
+                    }
                 }
-                stderr_run_handle.lock().await.stderr_line(line.clone());
-                buf.clear();
             }
-        });
+            .instrument(tracing::Span::current()),
+        );
 
-        tokio::select! {
-            _ = time::sleep(self.timeout) => {
-                if let Err(err) = child.kill().await {
-                    eprintln!("{}: failed to kill process: {:?}", target.key, err);
+        // `None` if the run timed out, `Some(None)` if its status is unknown
+        let (code, rusage) = tokio::select! {
+            _ = time::sleep_until(deadline) => {
+                match process.kill() {
+                    Ok(()) => {
+                        tracing::warn!("killed due to missed deadline!");
+                        (None, collect(exited.await).map(|(_, rusage)| rusage))
+                    }
+                    Err(err) => {
+                        // still reaped by the blocking thread once it exits
+                        tracing::error!("failed to kill process: {:?}", err);
+                        run_handle.lock().await.kill_failed(err.to_string());
+                        (None, None)
+                    }
                 }
-                eprintln!("{}: killed due to missed deadline!", target.key);
-                run_handle.lock().await.timeout();
             }
-            status = child.wait() => {
-                let status = status.expect("child process encountered an error");
-                if print_stderr || print_stdout {
-                    println!("{}: {}", target.key, status);
+            waited = &mut exited => match collect(waited) {
+                Some((status, rusage)) => {
+                    if print_stderr || print_stdout {
+                        tracing::info!("{}", status);
+                    } else {
+                        tracing::debug!("{}", status);
+                    }
+                    (Some(status.code()), Some(rusage))
                 }
-                run_handle.lock().await.exit(status.code());
-            }
+                None => (Some(None), None),
+            },
         };
+        let wall_time = started_at.elapsed();
+
+        // Process remaining output so that all flags are submitted before the run counts as done.
+        // Background processes holding on to the pipes don't get to extend the run's deadline.
+        let output_deadline = deadline.max(time::Instant::now() + OUTPUT_GRACE_PERIOD);
+        let output = futures::future::join(&mut stdout_task, &mut stderr_task);
+        if time::timeout_at(output_deadline, output).await.is_err() {
+            tracing::warn!("output pipes still open, ignoring the rest");
+            stdout_task.abort();
+            stderr_task.abort();
+        }
+
+        let mut run_handle = run_handle.lock().await;
+        match code {
+            Some(code) => {
+                let code_label = code.map_or_else(|| "signal".into(), |code| code.to_string());
+                metrics().run_exits.inc(&[&target.key, &code_label]);
+                run_handle.exit(code)
+            }
+            None => {
+                metrics().run_timeouts.inc(&[&target.key]);
+                run_handle.timeout()
+            }
+        }
+        run_handle.stats(wall_time, rusage);
     }
 }
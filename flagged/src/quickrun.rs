@@ -0,0 +1,229 @@
+//! `flagged run`: run an exploit against a few selected targets, then summarize runs and verdicts.
+
+use crate::color::{paint, Color};
+use crate::ctfapi::VerdictClass;
+use crate::events::{Event, EventPayload};
+use crate::runner::Runner;
+
+use clap::Args;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// How long to wait for verdicts after the last run
+const VERDICT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Args, Debug)]
+pub struct RunOpts {
+    /// Primary key of a target to run against, e.g. its IP or team1/ssh for composite keys. Can be passed multiple times
+    #[clap(short = 't', long = "target", alias = "only", required = true)]
+    targets: Vec<String>,
+    /// Number of runs per target
+    #[clap(short = 'n', long = "times", default_value = "1")]
+    times: u32,
+}
+
+struct RunSummary {
+    key: String,
+    started_at: chrono::DateTime<chrono::Utc>,
+    duration: Option<chrono::Duration>,
+    exit: Option<Option<i32>>,
+    flags: Vec<FlagSummary>,
+}
+
+struct FlagSummary {
+    flag: String,
+    is_unique: bool,
+    verdict: Option<String>,
+    /// Why flagged gave up on the flag without a verdict
+    dropped: Option<String>,
+}
+
+#[derive(Default)]
+struct Summary {
+    order: Vec<Uuid>,
+    runs: HashMap<Uuid, RunSummary>,
+}
+
+impl Summary {
+    fn record(&mut self, event: Event) {
+        match event.payload {
+            EventPayload::RunStart(run) => {
+                self.order.push(run.id);
+                self.runs.insert(
+                    run.id,
+                    RunSummary {
+                        key: run.key,
+                        started_at: event.timestamp,
+                        duration: None,
+                        exit: None,
+                        flags: Vec::new(),
+                    },
+                );
+            }
+            EventPayload::RunExit { run, exit_code } => {
+                if let Some(summary) = self.runs.get_mut(&run.id) {
+                    summary.duration = Some(event.timestamp - summary.started_at);
+                    summary.exit = Some(exit_code);
+                }
+            }
+            EventPayload::RunTimeout(run) => {
+                if let Some(summary) = self.runs.get_mut(&run.id) {
+                    summary.duration = Some(event.timestamp - summary.started_at);
+                }
+            }
+            EventPayload::FlagMatch {
+                run,
+                flag,
+                is_unique,
+            } => {
+                if let Some(summary) = self.runs.get_mut(&run.id) {
+                    summary.flags.push(FlagSummary {
+                        flag,
+                        is_unique,
+                        verdict: None,
+                        dropped: None,
+                    });
+                }
+            }
+            EventPayload::FlagVerdict { run, flag, verdict } => {
+                if let Some(summary) = self.runs.get_mut(&run.id) {
+                    for entry in summary.flags.iter_mut() {
+                        if entry.flag == flag && entry.is_unique {
+                            entry.verdict = Some(verdict.clone());
+                        }
+                    }
+                }
+            }
+            EventPayload::FlagDropped { run, flag, reason } => {
+                if let Some(summary) = self.runs.get_mut(&run.id) {
+                    for entry in summary.flags.iter_mut() {
+                        if entry.flag == flag && entry.is_unique {
+                            entry.dropped = Some(reason.clone());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn missing_verdicts(&self) -> usize {
+        self.runs
+            .values()
+            .flat_map(|run| run.flags.iter())
+            .filter(|flag| flag.is_unique && flag.verdict.is_none() && flag.dropped.is_none())
+            .count()
+    }
+
+    /// Prints all runs and returns whether every run exited cleanly and
+    /// at least one flag wasn't rejected by the gameserver.
+    fn print(&self) -> bool {
+        let mut clean_exits = true;
+        let mut good_flags = 0;
+
+        println!("{}", paint("Summary:", Color::Bold));
+        for run in self.order.iter().filter_map(|id| self.runs.get(id)) {
+            let duration = run
+                .duration
+                .and_then(|duration| duration.to_std().ok())
+                .map(|duration| format!("{:.2?}", duration))
+                .unwrap_or_else(|| "?".into());
+            let status = match run.exit {
+                Some(Some(0)) => paint("exit 0", Color::Green),
+                Some(Some(code)) => paint(format!("exit {}", code), Color::Red),
+                Some(None) => paint("killed by signal", Color::Red),
+                None => paint("timeout", Color::Red),
+            };
+            clean_exits &= run.exit == Some(Some(0));
+            println!(
+                "{} | {} after {}, {} flag(s)",
+                paint(&run.key, Color::Cyan),
+                status,
+                duration,
+                run.flags.len()
+            );
+
+            for flag in &run.flags {
+                let verdict = match (&flag.verdict, flag.is_unique) {
+                    (_, false) => paint("duplicate, not submitted", Color::Yellow),
+                    (None, true) => match &flag.dropped {
+                        Some(reason) => paint(format!("not submitted: {}", reason), Color::Yellow),
+                        None => paint("no verdict", Color::Yellow),
+                    },
+                    (Some(verdict), true) => {
+                        let class = VerdictClass::of(verdict);
+                        if !class.is_rejected() {
+                            good_flags += 1;
+                        }
+                        paint(verdict, Color::of_verdict(class))
+                    }
+                };
+                println!("    {} -> {}", flag.flag, verdict);
+            }
+        }
+
+        clean_exits && good_flags > 0
+    }
+}
+
+/// Runs the selected targets `times` times and prints a summary. Returns whether the exploit worked.
+pub async fn run(
+    mut runner: Runner,
+    mut events: mpsc::UnboundedReceiver<Event>,
+    opts: RunOpts,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let unknown = opts
+        .targets
+        .iter()
+        .filter(|key| !runner.targets.iter().any(|target| &target.key == *key))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        let known = runner
+            .targets
+            .iter()
+            .map(|target| target.key.as_str())
+            .collect::<Vec<_>>();
+        return Err(format!(
+            "unknown target(s) {:?}, expected one of {:?}",
+            unknown, known
+        )
+        .into());
+    }
+    runner
+        .targets
+        .retain(|target| opts.targets.contains(&target.key));
+
+    if let Some(clock) = runner.clock.as_mut() {
+        match clock.current_round().await {
+            Ok(round) => runner.round = Some(round),
+            Err(err) => tracing::warn!("failed to determine the current round: {}", err),
+        }
+    }
+
+    let mut summary = Summary::default();
+    for i in 0..opts.times {
+        println!(
+            "{}",
+            paint(format!("Run {}/{}", i + 1, opts.times), Color::Bold)
+        );
+        runner.run_interval().await;
+        while let Ok(event) = events.try_recv() {
+            summary.record(event);
+        }
+    }
+
+    // verdicts are published asynchronously
+    let deadline = tokio::time::Instant::now() + VERDICT_TIMEOUT;
+    while summary.missing_verdicts() > 0 {
+        match tokio::time::timeout_at(deadline, events.recv()).await {
+            Ok(Some(event)) => summary.record(event),
+            _ => break,
+        }
+    }
+
+    runner.events_session.end();
+    runner.events_session.flush().await;
+    Ok(summary.print())
+}
@@ -0,0 +1,156 @@
+use crate::config::Target;
+use crate::events;
+use crate::flaghandler::FlagHandler;
+use crate::metrics::metrics;
+use crate::proc::ProcessConfig;
+use crate::ticks::Clock;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex};
+
+pub struct Runner {
+    pub interval: Duration,
+    pub concurrency: u64,
+    pub targets: Vec<Arc<Target>>,
+    pub process_config: ProcessConfig,
+    pub flag_handler: Arc<Mutex<FlagHandler>>,
+    pub events_session: events::Session,
+    /// Aligns intervals to the game's ticks instead of running them every `interval`
+    pub clock: Option<Clock>,
+    /// Current round, passed to exploits as ROUND
+    pub round: Option<u64>,
+}
+
+impl Runner {
+    /// Runs every target once, then waits for all pending flags to be submitted.
+    pub async fn run_interval(&mut self) {
+        self.events_session.start_interval(self.round);
+        tracing::info!(
+            interval = self.events_session.interval(),
+            round = self.round,
+            "Starting interval..."
+        );
+        metrics().intervals.inc(&[]);
+
+        let mut jobs = FuturesUnordered::new();
+        // NOTE: `active` vastly over-estimates actives jobs for well-behaving exploits
+        let mut active = 0;
+        for target in &self.targets {
+            if active == self.concurrency {
+                jobs.next().await;
+                active -= 1;
+            }
+            let run_handle = self.events_session.run_handle(target);
+            jobs.push(
+                self.process_config
+                    .spawn(target.clone(), run_handle, self.round),
+            );
+            active += 1;
+        }
+
+        // drain active jobs
+        while active != 0 {
+            jobs.next().await;
+            active -= 1;
+        }
+
+        {
+            let mut flag_handler = self.flag_handler.lock().await;
+            let queue_depth = flag_handler.queue_depth();
+            if queue_depth > 0 {
+                tracing::info!(
+                    "Waiting for {} queued flag(s) to be submitted...",
+                    queue_depth
+                );
+            }
+            flag_handler.flush().await;
+        }
+
+        self.events_session.end_interval();
+    }
+
+    /// Runs intervals back to back until `stop` is set. A running interval is always completed.
+    pub async fn run(&mut self, mut stop: watch::Receiver<bool>) {
+        while !*stop.borrow() {
+            if self.clock.is_some() {
+                if !self.wait_for_tick(&mut stop).await {
+                    break;
+                }
+                self.run_interval().await;
+                continue;
+            }
+
+            let started_at = Instant::now();
+            self.run_interval().await;
+
+            let elapsed = started_at.elapsed();
+            let lateness = elapsed.saturating_sub(self.interval);
+            metrics().interval_lateness.set(&[], lateness.as_secs_f64());
+            if elapsed >= self.interval {
+                tracing::warn!(
+                    "Late! Missed interval deadline by {:?}",
+                    elapsed - self.interval
+                );
+            } else {
+                tracing::info!("Done! Snoozing for {:?}", self.interval - elapsed);
+                let deadline = tokio::time::Instant::from_std(started_at + self.interval);
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => {}
+                    _ = stopped(&mut stop) => {}
+                }
+            }
+        }
+        self.events_session.end();
+        self.events_session.flush().await;
+    }
+
+    /// Sleeps until the next tick (plus offset) and updates `round`. Falls back to
+    /// waiting `interval` if the round clock is unavailable. Returns false if stopped.
+    async fn wait_for_tick(&mut self, stop: &mut watch::Receiver<bool>) -> bool {
+        let clock = self.clock.as_mut().unwrap();
+        let next = tokio::select! {
+            next = clock.next_tick() => next,
+            _ = stopped(stop) => return false,
+        };
+
+        let (delay, round) = match next {
+            Ok((round, at)) => {
+                if let Some(previous) = self.round.filter(|previous| round > previous + 1) {
+                    tracing::warn!("Late! Skipped {} round(s)", round - previous - 1);
+                    metrics()
+                        .rounds_skipped
+                        .add(&[], (round - previous - 1) as f64);
+                }
+                let delay = (at - chrono::Utc::now()).to_std().unwrap_or_default();
+                tracing::info!("Snoozing for {:?} until round {}", delay, round);
+                (delay, Some(round))
+            }
+            Err(err) => {
+                tracing::error!(
+                    "Failed to determine the next tick, waiting {:?}: {}",
+                    self.interval,
+                    err
+                );
+                (self.interval, None)
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stopped(stop) => return false,
+        }
+        self.round = round;
+        true
+    }
+}
+
+/// Resolves once `stop` is set. Never resolves if the sender is gone.
+async fn stopped(stop: &mut watch::Receiver<bool>) {
+    while !*stop.borrow() {
+        if stop.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use crate::flagformat::Transform;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Lines longer than this are split up before being printed or published
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 4096;
+// Bytes of a split line that are re-scanned so flags crossing the split are still found
+const MAX_FLAG_LENGTH: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Base64,
+    Hex,
+}
+
+impl Encoding {
+    fn candidate_regex(self) -> Regex {
+        match self {
+            Encoding::Base64 => Regex::new(r"[A-Za-z0-9+/_-]{16,}={0,2}").unwrap(),
+            Encoding::Hex => Regex::new(r"(?:[0-9a-fA-F]{2}){8,}").unwrap(),
+        }
+    }
+
+    /// Number of encoded bytes up to the end of `decoded` bytes
+    fn encoded_len(self, decoded: usize) -> usize {
+        match self {
+            Encoding::Base64 => (decoded * 4).div_ceil(3),
+            Encoding::Hex => decoded * 2,
+        }
+    }
+
+    fn decode(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Encoding::Base64 => decode_base64(data),
+            Encoding::Hex => decode_hex(data),
+        }
+    }
+}
+
+pub struct FlagMatcher {
+    flag_regex: Regex,
+    transforms: Vec<Transform>,
+    decoders: Vec<(Encoding, Regex)>,
+}
+
+impl FlagMatcher {
+    /// Fails if the regexes can't be combined, e.g. because of duplicate group names or the size limit.
+    pub fn new(
+        flag_regexes: &[Regex],
+        transforms: Vec<Transform>,
+        decode: &[Encoding],
+    ) -> Result<Self, regex::Error> {
+        let flag_regex = match flag_regexes {
+            [regex] => regex.clone(),
+            regexes => {
+                let alternatives = regexes
+                    .iter()
+                    .map(|re| format!("(?:{})", re.as_str()))
+                    .collect::<Vec<_>>();
+                Regex::new(&alternatives.join("|"))?
+            }
+        };
+        let decoders = decode
+            .iter()
+            .map(|&encoding| (encoding, encoding.candidate_regex()))
+            .collect();
+        Ok(FlagMatcher {
+            flag_regex,
+            transforms,
+            decoders,
+        })
+    }
+
+    /// Finds all flags in `haystack` that end after `skip` bytes.
+    pub fn find(&self, haystack: &[u8], skip: usize) -> Vec<String> {
+        let mut flags = Vec::new();
+        for m in self.flag_regex.find_iter(haystack) {
+            if m.end() > skip {
+                flags.push(self.normalize(m.as_bytes()));
+            }
+        }
+        for (encoding, candidate_regex) in &self.decoders {
+            for candidate in candidate_regex.find_iter(haystack) {
+                if candidate.end() <= skip {
+                    continue;
+                }
+                if let Some(decoded) = encoding.decode(candidate.as_bytes()) {
+                    // a candidate crossing `skip` was decoded up to there before, and so were its flags
+                    for m in self.flag_regex.find_iter(&decoded) {
+                        if candidate.start() + encoding.encoded_len(m.end()) > skip {
+                            flags.push(self.normalize(m.as_bytes()));
+                        }
+                    }
+                }
+            }
+        }
+        flags
+    }
+
+    fn normalize(&self, flag: &[u8]) -> String {
+        let flag = String::from_utf8_lossy(flag).to_string();
+        self.transforms
+            .iter()
+            .fold(flag, |flag, transform| transform.apply(flag))
+    }
+}
+
+pub struct Segment {
+    pub line: String,
+    pub flags: Vec<String>,
+}
+
+/// Splits a raw byte stream into lines of bounded length and matches flags on the fly.
+pub struct LineScanner {
+    matcher: Option<Arc<FlagMatcher>>,
+    max_line_length: usize,
+    buf: Vec<u8>,
+    carry: Vec<u8>,
+}
+
+impl LineScanner {
+    pub fn new(matcher: Option<Arc<FlagMatcher>>, max_line_length: usize) -> Self {
+        LineScanner {
+            matcher,
+            max_line_length: max_line_length.max(1),
+            buf: Vec::new(),
+            carry: Vec::new(),
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Segment> {
+        self.buf.extend_from_slice(data);
+        let mut segments = Vec::new();
+        loop {
+            let limit = self.buf.len().min(self.max_line_length);
+            if let Some(pos) = self.buf[..limit].iter().position(|&b| b == b'\n') {
+                let line = self.buf.drain(..=pos).collect::<Vec<_>>();
+                segments.push(self.segment(&line[..pos], true));
+            } else if self.buf.len() >= self.max_line_length {
+                let line = self.buf.drain(..limit).collect::<Vec<_>>();
+                segments.push(self.segment(&line, false));
+            } else {
+                break;
+            }
+        }
+        segments
+    }
+
+    pub fn finish(&mut self) -> Option<Segment> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let line = std::mem::take(&mut self.buf);
+        Some(self.segment(&line, true))
+    }
+
+    fn segment(&mut self, line: &[u8], complete: bool) -> Segment {
+        let flags = match &self.matcher {
+            Some(matcher) => {
+                let skip = self.carry.len();
+                self.carry.extend_from_slice(line);
+                let flags = matcher.find(&self.carry, skip);
+                if complete {
+                    self.carry.clear();
+                } else {
+                    let keep = self.carry.len().min(MAX_FLAG_LENGTH);
+                    self.carry.drain(..self.carry.len() - keep);
+                }
+                flags
+            }
+            None => Vec::new(),
+        };
+        Segment {
+            line: String::from_utf8_lossy(line).to_string(),
+            flags,
+        }
+    }
+}
+
+fn decode_hex(data: &[u8]) -> Option<Vec<u8>> {
+    fn nibble(c: u8) -> Option<u8> {
+        (c as char).to_digit(16).map(|d| d as u8)
+    }
+    let pairs = data.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| Some(nibble(pair[0])? << 4 | nibble(pair[1])?))
+        .collect()
+}
+
+fn decode_base64(data: &[u8]) -> Option<Vec<u8>> {
+    fn sextet(c: u8) -> Option<u32> {
+        Some(match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        } as u32)
+    }
+    let data = data.split(|&c| c == b'=').next().unwrap_or_default();
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut acc = 0;
+        for (i, &c) in chunk.iter().enumerate() {
+            acc |= sextet(c)? << (18 - 6 * i);
+        }
+        let bytes = acc.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(decode: &[Encoding]) -> Arc<FlagMatcher> {
+        let regex = Regex::new(r"FLAG\{[a-z]+\}").unwrap();
+        Arc::new(FlagMatcher::new(&[regex], Vec::new(), decode).unwrap())
+    }
+
+    fn flags(segments: &[Segment]) -> Vec<&str> {
+        segments
+            .iter()
+            .flat_map(|segment| segment.flags.iter().map(String::as_str))
+            .collect()
+    }
+
+    #[test]
+    fn flags_split_across_reads() {
+        let mut scanner = LineScanner::new(Some(matcher(&[])), 64);
+        assert!(scanner.feed(b"foo FLAG{ab").is_empty());
+        let segments = scanner.feed(b"cd} bar\nbaz");
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].line, "foo FLAG{abcd} bar");
+        assert_eq!(flags(&segments), ["FLAG{abcd}"]);
+
+        let rest = scanner.finish().unwrap();
+        assert_eq!(rest.line, "baz");
+        assert!(scanner.finish().is_none());
+    }
+
+    #[test]
+    fn overlong_lines_are_split() {
+        let mut scanner = LineScanner::new(Some(matcher(&[])), 8);
+        let segments = scanner.feed(b"0123456789abcdefgh\n");
+        let lines = segments.iter().map(|s| s.line.as_str()).collect::<Vec<_>>();
+        assert_eq!(lines, ["01234567", "89abcdef", "gh"]);
+    }
+
+    #[test]
+    fn flags_crossing_a_split_are_found_once() {
+        let mut scanner = LineScanner::new(Some(matcher(&[])), 16);
+        // split after "FLAG{ab", the rest is re-scanned with the carried bytes
+        let segments = scanner.feed(b"xxxxxxxxxFLAG{abcdef}\n");
+        assert_eq!(segments.len(), 2);
+        assert_eq!(flags(&segments), ["FLAG{abcdef}"]);
+
+        // a flag completely before the split isn't reported again from the carry
+        let segments = scanner.feed(b"FLAG{one}xxxxxxxxxxxxxxxxFLAG{two}\n");
+        assert_eq!(flags(&segments), ["FLAG{one}", "FLAG{two}"]);
+    }
+
+    #[test]
+    fn carry_is_bounded() {
+        let mut scanner = LineScanner::new(Some(matcher(&[])), 100);
+        for _ in 0..10 {
+            scanner.feed(&[b'x'; 100]);
+            assert!(scanner.carry.len() <= MAX_FLAG_LENGTH);
+        }
+        // a flag starting before the carried window is lost, but nothing breaks
+        let mut data = b"FLAG{".to_vec();
+        data.extend_from_slice(&[b'a'; MAX_FLAG_LENGTH + 100]);
+        data.extend_from_slice(b"}\n");
+        assert!(flags(&scanner.feed(&data)).is_empty());
+        assert!(scanner.carry.is_empty());
+    }
+
+    #[test]
+    fn decodes_hex_and_base64() {
+        assert_eq!(decode_hex(b"464c4147").unwrap(), b"FLAG");
+        assert_eq!(decode_hex(b"464C4147").unwrap(), b"FLAG");
+        assert_eq!(decode_hex(b"464c414"), None);
+        assert_eq!(decode_hex(b"46zz"), None);
+
+        assert_eq!(decode_base64(b"RkxBRw==").unwrap(), b"FLAG");
+        assert_eq!(decode_base64(b"RkxBRw").unwrap(), b"FLAG");
+        // url-safe alphabet
+        assert_eq!(decode_base64(b"-_-_").unwrap(), [0xfb, 0xff, 0xbf]);
+        assert_eq!(decode_base64(b"Rk!BRw=="), None);
+        // a dangling sextet doesn't make up a byte
+        assert_eq!(decode_base64(b"RkxBR").unwrap(), b"FLA");
+    }
+
+    #[test]
+    fn finds_encoded_flags() {
+        let matcher = matcher(&[Encoding::Base64, Encoding::Hex]);
+        // base64 and hex of FLAG{encoded}
+        let line = b"token RkxBR3tlbmNvZGVkfQ== hex 464c41477b656e636f6465647d";
+        assert_eq!(matcher.find(line, 0), ["FLAG{encoded}", "FLAG{encoded}"]);
+        assert!(matcher.find(line, line.len()).is_empty());
+    }
+
+    #[test]
+    fn encoded_flags_crossing_a_split_are_found_once() {
+        let mut scanner = LineScanner::new(Some(matcher(&[Encoding::Base64, Encoding::Hex])), 32);
+        // base64 of "FLAG{encoded}yyy...", split after the part that holds the flag
+        let segments = scanner
+            .feed(b"token RkxBR3tlbmNvZGVkfXl5eXl5eXl5eXl5eXl5eXl5eXl5eXl5eXl5eXl5eQ== end\n");
+        assert_eq!(segments.len(), 3);
+        assert_eq!(flags(&segments), ["FLAG{encoded}"]);
+
+        // split inside the encoded flag
+        let segments = scanner.feed(b"xxxxxxxxxxxxxxxxxxxxx 464c41477b656e636f6465647d end\n");
+        assert_eq!(segments.len(), 2);
+        assert_eq!(flags(&segments), ["FLAG{encoded}"]);
+    }
+}
@@ -1,71 +1,310 @@
 use crate::ctfapi::{Flag, Submitter};
+use crate::events::SessionHandle;
+use crate::metrics::metrics;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
 
-const BATCH_SIZE_LIMIT: usize = 50;
-const BATCH_TIME_LIMIT: Duration = Duration::from_millis(1000);
+/// Reasons for giving up on flags, see `Flag::give_up`
+const EXPIRED: &str = "expired";
+const UNANSWERED: &str = "unanswered";
+
+/// How flags are batched and paced. The backend's `Submitter::policy` is the default, the config's `submission`
+/// table overrides single settings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubmissionPolicy {
+    /// Maximum number of flags per batch (e.g. per connection or request)
+    pub batch_size: usize,
+    /// Seconds to wait for more flags before submitting an incomplete batch
+    pub flush_latency: f64,
+    /// Maximum number of flags per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<f64>,
+    /// Number of flags that may be submitted at once before `rate_limit` kicks in. Defaults to `batch_size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<usize>,
+    /// Seconds to wait after the first failed submission. Doubles with every failure
+    pub backoff_initial: f64,
+    /// Upper bound for the wait between retries
+    pub backoff_max: f64,
+}
+
+impl Default for SubmissionPolicy {
+    fn default() -> Self {
+        SubmissionPolicy {
+            batch_size: 50,
+            flush_latency: 1.0,
+            rate_limit: None,
+            burst: None,
+            backoff_initial: 1.0,
+            backoff_max: 60.0,
+        }
+    }
+}
+
+impl SubmissionPolicy {
+    pub fn check(&self) -> Result<(), String> {
+        if self.batch_size == 0 {
+            return Err("batch_size must be at least 1".into());
+        }
+        if self.burst == Some(0) {
+            return Err("burst must be at least 1".into());
+        }
+        let durations = [
+            ("flush_latency", self.flush_latency),
+            ("backoff_initial", self.backoff_initial),
+            ("backoff_max", self.backoff_max),
+        ];
+        for (name, value) in durations {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(format!("{} must be a non-negative number of seconds", name));
+            }
+        }
+        if let Some(rate_limit) = self.rate_limit {
+            if !(rate_limit.is_finite() && rate_limit > 0.0) {
+                return Err("rate_limit must be positive".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Batches never exceed the token bucket's capacity
+    fn max_batch_size(&self) -> usize {
+        match (self.rate_limit, self.burst) {
+            (Some(_), Some(burst)) => self.batch_size.min(burst),
+            _ => self.batch_size,
+        }
+    }
+
+    /// Exponential backoff with jitter: between half and all of `backoff_initial * 2^failures`, capped at `backoff_max`
+    fn backoff(&self, failures: u32) -> Duration {
+        let delay =
+            (self.backoff_initial * 2f64.powi(failures.min(32) as i32)).min(self.backoff_max);
+        Duration::from_secs_f64(delay * rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: usize) -> Self {
+        TokenBucket {
+            rate,
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Waits until `n` tokens are available and takes them
+    async fn acquire(&mut self, n: usize) {
+        let n = (n as f64).min(self.capacity);
+        loop {
+            let now = Instant::now();
+            let refill = (now - self.last).as_secs_f64() * self.rate;
+            self.tokens = (self.tokens + refill).min(self.capacity);
+            self.last = now;
+            if self.tokens >= n {
+                self.tokens -= n;
+                return;
+            }
+            let missing = n - self.tokens;
+            tokio::time::sleep(Duration::from_secs_f64(missing / self.rate)).await;
+        }
+    }
+}
 
 pub struct FlagBatcher {
     pub tx: mpsc::Sender<Flag>,
     pub flushtx: mpsc::Sender<oneshot::Sender<()>>,
+    queue_depth: Arc<AtomicUsize>,
 }
+
+/// Drops expired flags and takes up to `batch_size` of the freshest ones.
+/// Older flags stay pending for the next batch.
+fn next_batch(
+    pending: &mut Vec<Flag>,
+    batch_size: usize,
+    flag_lifetime: Option<Duration>,
+) -> Vec<Flag> {
+    if let Some(lifetime) = flag_lifetime.and_then(|l| chrono::Duration::from_std(l).ok()) {
+        pending.retain(|flag| {
+            let expired = flag.age() > lifetime;
+            if expired {
+                flag.give_up(EXPIRED);
+            }
+            !expired
+        });
+    }
+    pending.sort_by_key(|flag| flag.captured_at);
+    let mut batch = pending.split_off(pending.len().saturating_sub(batch_size));
+    batch.reverse();
+    batch
+}
+
+/// Removes flags that are done (submitted or dropped) from the queue depth
+fn done(queue_depth: &AtomicUsize, flags: usize) {
+    let depth = queue_depth.fetch_sub(flags, Ordering::Relaxed) - flags;
+    metrics().queue_depth.set(&[], depth as f64);
+}
+
 impl FlagBatcher {
-    pub fn start(submitter: Box<dyn Submitter + Sync + Send>) -> Self {
-        let (tx, rx) = mpsc::channel(BATCH_SIZE_LIMIT);
+    /// Flags older than `flag_lifetime` are not submitted anymore. Every submission attempt is published to `events`.
+    pub fn start(
+        submitter: Box<dyn Submitter + Sync + Send>,
+        policy: SubmissionPolicy,
+        flag_lifetime: Option<Duration>,
+        events: SessionHandle,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(policy.batch_size);
         let (flushtx, flushrx) = mpsc::channel(1);
-        tokio::spawn(Self::watchdog(rx, flushrx, submitter));
-        FlagBatcher { tx, flushtx }
+        let queue_depth = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(Self::watchdog(
+            rx,
+            flushrx,
+            submitter,
+            policy,
+            flag_lifetime,
+            queue_depth.clone(),
+            events,
+        ));
+        FlagBatcher {
+            tx,
+            flushtx,
+            queue_depth,
+        }
+    }
+
+    /// Number of flags waiting for a verdict, including the batch that is being submitted
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Relaxed)
     }
 
     async fn watchdog(
         mut rx: mpsc::Receiver<Flag>,
         mut flushrx: mpsc::Receiver<oneshot::Sender<()>>,
         submitter: Box<dyn Submitter + Sync + Send>,
+        policy: SubmissionPolicy,
+        flag_lifetime: Option<Duration>,
+        queue_depth: Arc<AtomicUsize>,
+        events: SessionHandle,
     ) {
+        let batch_size = policy.max_batch_size();
+        let flush_latency = Duration::from_secs_f64(policy.flush_latency);
+        let mut bucket = policy
+            .rate_limit
+            .map(|rate| TokenBucket::new(rate, policy.burst.unwrap_or(policy.batch_size)));
         let mut pending = Vec::new();
+        let mut acks: Vec<oneshot::Sender<()>> = Vec::new();
+        // consecutive failed submissions, for the backoff
+        let mut failures = 0;
         loop {
-            tokio::select! {
-                chan = flushrx.recv() => {
-                    chan.unwrap().send(()).unwrap();
-                    continue;
+            if pending.is_empty() {
+                for ack_tx in acks.drain(..) {
+                    let _ = ack_tx.send(());
                 }
-                item = rx.recv() => {
-                    pending.push(item.unwrap());
+                // biased: flags queued before a flush request have to be part of the flush
+                tokio::select! {
+                    biased;
+                    item = rx.recv() => match item {
+                        Some(flag) => pending.push(flag),
+                        None => return,
+                    },
+                    chan = flushrx.recv() => {
+                        if let Some(ack_tx) = chan {
+                            let _ = ack_tx.send(());
+                        }
+                        continue;
+                    }
                 }
             }
 
-            let mut ack_tx = None;
-
-            let deadline = tokio::time::Instant::now() + BATCH_TIME_LIMIT;
-            loop {
+            // wait a bit for more flags, unless somebody is waiting for a flush or this is a retry
+            let deadline = Instant::now() + flush_latency;
+            while failures == 0 && acks.is_empty() && pending.len() < batch_size {
                 tokio::select! {
-                    _ = tokio::time::sleep_until(deadline) => {
+                    biased;
+                    item = rx.recv() => match item {
+                        Some(flag) => pending.push(flag),
+                        None => break,
+                    },
+                    chan = flushrx.recv() => {
+                        acks.extend(chan);
                         break;
                     }
-                    item = rx.recv() => {
-                        pending.push(item.unwrap());
-                        if pending.len() >= BATCH_SIZE_LIMIT { break; }
-                    }
-                    chan = flushrx.recv() => {
-                        ack_tx = Some(chan.unwrap());
+                    _ = tokio::time::sleep_until(deadline) => {
                         break;
                     }
                 }
             }
+            // everything queued in the meantime competes for the next batch
+            while let Ok(flag) = rx.try_recv() {
+                pending.push(flag);
+            }
 
-            while let Err(err) = tokio::task::block_in_place(|| submitter.submit_batch(&pending)) {
-                eprintln!("failed to submit batch: {:?}", err);
-                eprintln!("retrying...");
-                tokio::time::sleep(BATCH_TIME_LIMIT).await;
+            let before = pending.len();
+            let mut batch = next_batch(&mut pending, batch_size, flag_lifetime);
+            // expired flags are done
+            done(&queue_depth, before - pending.len() - batch.len());
+            if batch.is_empty() {
+                continue;
             }
-            pending.clear();
-            if let Some(ack_tx) = ack_tx {
-                ack_tx.send(()).unwrap();
+            if let Some(bucket) = bucket.as_mut() {
+                bucket.acquire(batch.len()).await;
+            }
+            let started_at = Instant::now();
+            let result = tokio::task::block_in_place(|| submitter.submit_batch(&batch));
+            let duration = started_at.elapsed();
+            metrics().submission_latency.observe(duration.as_secs_f64());
+            // flags that received a verdict are done, even if the connection died afterwards
+            let before = batch.len();
+            batch.retain(|flag| !flag.has_verdict());
+            done(&queue_depth, before - batch.len());
+            let error = result.as_ref().err().map(|err| err.to_string());
+            events.submit_batch(before, before - batch.len(), duration, error);
+            match result {
+                Err(err) if !batch.is_empty() => {
+                    let backoff = policy.backoff(failures);
+                    failures += 1;
+                    tracing::warn!(
+                        failures,
+                        "failed to submit batch ({} of {} flags without verdict), retrying in {:.1?}: {:?}",
+                        batch.len(),
+                        before,
+                        backoff,
+                        err
+                    );
+                    // the rest competes with newer flags again, and is dropped once it expires
+                    pending.extend(batch);
+                    tokio::time::sleep(backoff).await;
+                }
+                _ => {
+                    failures = 0;
+                    if !batch.is_empty() {
+                        tracing::warn!("submitter returned no verdict for {} flag(s)", batch.len());
+                        for flag in &batch {
+                            flag.give_up(UNANSWERED);
+                        }
+                    }
+                    done(&queue_depth, batch.len());
+                }
             }
         }
     }
 
     pub async fn submit(&mut self, flag: Flag) {
+        let depth = self.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics().queue_depth.set(&[], depth as f64);
         self.tx
             .send(flag)
             .await
@@ -78,3 +317,46 @@ impl FlagBatcher {
         rx.await.unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_is_exponential_with_jitter() {
+        let policy = SubmissionPolicy {
+            backoff_initial: 1.0,
+            backoff_max: 10.0,
+            ..Default::default()
+        };
+        for (failures, max) in [
+            (0, 1.0),
+            (1, 2.0),
+            (2, 4.0),
+            (3, 8.0),
+            (4, 10.0),
+            (40, 10.0),
+        ] {
+            let backoff = policy.backoff(failures).as_secs_f64();
+            assert!(
+                backoff >= max / 2.0 && backoff <= max,
+                "{} {}",
+                failures,
+                backoff
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket_limits_rate() {
+        let mut bucket = TokenBucket::new(10.0, 5);
+        let started_at = Instant::now();
+        bucket.acquire(5).await;
+        assert_eq!(started_at.elapsed(), Duration::ZERO);
+        // 5 more tokens after 0.5s, then a full bucket (capped at 5) after another 0.5s
+        bucket.acquire(5).await;
+        bucket.acquire(10).await;
+        let elapsed = started_at.elapsed().as_secs_f64();
+        assert!((0.99..1.1).contains(&elapsed), "{}", elapsed);
+    }
+}
@@ -0,0 +1,193 @@
+//! Include/exclude rules on target fields, e.g. `NAME != "NOP"`, `IP in 10.32.0.0/16` or `NAME =~ ^team`.
+
+use ipnet::IpNet;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::config::value_to_string;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Eq(String),
+    Matches(Regex),
+    In(IpNet),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Filter {
+    source: String,
+    key: String,
+    op: Op,
+    negated: bool,
+}
+
+impl Filter {
+    /// Missing fields never match, so `NAME != "NOP"` holds for targets without a NAME.
+    pub fn matches(&self, target: &HashMap<String, Value>) -> bool {
+        let matches = target.get(&self.key).is_some_and(|value| {
+            let value = value_to_string(value);
+            match &self.op {
+                Op::Eq(expected) => &value == expected,
+                Op::Matches(regex) => regex.is_match(&value),
+                Op::In(net) => IpAddr::from_str(&value).is_ok_and(|ip| net.contains(&ip)),
+            }
+        });
+        matches != self.negated
+    }
+}
+
+/// Targets are selected if they match every include and no exclude filter.
+pub fn is_selected(
+    target: &HashMap<String, Value>,
+    include: &[Filter],
+    exclude: &[Filter],
+) -> bool {
+    include.iter().all(|filter| filter.matches(target))
+        && !exclude.iter().any(|filter| filter.matches(target))
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+impl FromStr for Filter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let key_len = s
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(s.len());
+        let (key, rest) = s.split_at(key_len);
+        if key.is_empty() {
+            return Err(format!("target filter {:?} doesn't start with a key", s));
+        }
+
+        let rest = rest.trim_start();
+        let operators = [
+            ("==", false),
+            ("!=", true),
+            ("=~", false),
+            ("!~", true),
+            ("not in ", true),
+            ("in ", false),
+        ];
+        let (op, negated, value) = operators
+            .iter()
+            .find_map(|(op, negated)| {
+                rest.strip_prefix(op)
+                    .map(|value| (op.trim(), *negated, unquote(value.trim())))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "target filter {:?} needs one of the operators ==, !=, =~, !~, in, not in",
+                    s
+                )
+            })?;
+
+        let op = match op {
+            "==" | "!=" => Op::Eq(value.to_string()),
+            "=~" | "!~" => Op::Matches(
+                Regex::new(value).map_err(|err| format!("target filter {:?}: {}", s, err))?,
+            ),
+            _ => Op::In(
+                value
+                    .parse()
+                    .map_err(|err| format!("target filter {:?}: {}", s, err))?,
+            ),
+        };
+
+        Ok(Filter {
+            source: s.to_string(),
+            key: key.to_string(),
+            op,
+            negated,
+        })
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> String {
+        filter.source
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn target(ip: &str, name: &str) -> HashMap<String, Value> {
+        HashMap::from([
+            ("IP".to_string(), json!(ip)),
+            ("NAME".to_string(), json!(name)),
+            ("IDX".to_string(), json!(3)),
+        ])
+    }
+
+    fn matches(filter: &str, target: &HashMap<String, Value>) -> bool {
+        filter.parse::<Filter>().unwrap().matches(target)
+    }
+
+    #[test]
+    fn operators() {
+        let nop = target("10.32.1.2", "NOP");
+        assert!(matches(r#"NAME == "NOP""#, &nop));
+        assert!(!matches(r#"NAME != "NOP""#, &nop));
+        assert!(matches("NAME!=WORKING", &nop));
+        assert!(matches("IDX == 3", &nop));
+        assert!(matches("NAME =~ ^N.P$", &nop));
+        assert!(!matches("NAME !~ '^N'", &nop));
+        assert!(matches("IP in 10.32.0.0/16", &nop));
+        assert!(!matches("IP not in 10.32.0.0/16", &nop));
+        assert!(!matches("IP in fd00::/8", &nop));
+        assert!(!matches("NAME in 10.32.0.0/16", &nop));
+        // missing fields never match
+        assert!(!matches("TEAM == NOP", &nop));
+        assert!(matches("TEAM != NOP", &nop));
+    }
+
+    #[test]
+    fn invalid_filters() {
+        for filter in ["", "== NOP", "NAME NOP", "NAME =~ (", "IP in 10.0.0.0"] {
+            assert!(filter.parse::<Filter>().is_err(), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn selection() {
+        let include = ["IP in 10.32.0.0/16".parse().unwrap()];
+        let exclude = ["NAME == NOP".parse().unwrap()];
+        assert!(is_selected(&target("10.32.1.2", "A"), &include, &exclude));
+        assert!(!is_selected(
+            &target("10.32.1.2", "NOP"),
+            &include,
+            &exclude
+        ));
+        assert!(!is_selected(&target("10.33.1.2", "A"), &include, &exclude));
+    }
+}
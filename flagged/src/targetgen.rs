@@ -0,0 +1,204 @@
+//! Generates target lists for regularly addressed games, e.g. `"IP": "10.60.{1..80}.2"`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TargetGenerator {
+    /// Team ids, e.g. "1..80" or "1..10,12,20..30". Can be omitted if a field contains a range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<String>,
+    /// Field templates. `{id}` is replaced by the team id, `{1..80}` also defines the ids
+    pub fields: BTreeMap<String, String>,
+    /// Team names by id, exposed as NAME
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub team_names: HashMap<String, String>,
+    /// Our own team id, which is never generated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub own_id: Option<u64>,
+}
+
+enum Part {
+    Literal(String),
+    Id,
+}
+
+/// Parses ids like `1..3, 5` into sorted, unique ids.
+fn parse_ids(spec: &str) -> Result<Vec<u64>, String> {
+    let mut ids = Vec::new();
+    for part in spec.split(',').map(str::trim) {
+        let parse = |s: &str| {
+            s.trim()
+                .parse::<u64>()
+                .map_err(|err| format!("invalid team id {:?} in {:?}: {}", s, spec, err))
+        };
+        match part.split_once("..") {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(format!("reversed range {:?} in {:?}", part, spec));
+                }
+                ids.extend(start..=end);
+            }
+            None => ids.push(parse(part)?),
+        }
+    }
+    // overlapping ranges would generate the same target twice
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// Splits a template into literals and `{id}` placeholders. Inline ranges are returned separately.
+fn parse_template(template: &str) -> Result<(Vec<Part>, Option<String>), String> {
+    let mut parts = Vec::new();
+    let mut range = None;
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(idx) = rest.find(['{', '}']) {
+        literal.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            literal.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let end = match (rest.starts_with('{'), rest.find('}')) {
+            (true, Some(end)) => end,
+            _ => return Err(format!("unbalanced braces in template {:?}", template)),
+        };
+        let placeholder = &rest[1..end];
+        if placeholder.contains("..") {
+            if range.as_deref().is_some_and(|range| range != placeholder) {
+                return Err(format!("conflicting ranges in template {:?}", template));
+            }
+            range = Some(placeholder.to_string());
+        } else if placeholder != "id" {
+            return Err(format!(
+                "unknown placeholder {{{}}} in template {:?}, expected {{id}} or a range",
+                placeholder, template
+            ));
+        }
+        parts.push(Part::Literal(std::mem::take(&mut literal)));
+        parts.push(Part::Id);
+        rest = &rest[end + 1..];
+    }
+    literal.push_str(rest);
+    parts.push(Part::Literal(literal));
+    Ok((parts, range))
+}
+
+impl TargetGenerator {
+    /// Expands into one target per team id with IDX, NAME (if known) and the templated fields.
+    pub fn expand(&self) -> Result<Vec<HashMap<String, Value>>, String> {
+        let mut ranges = self.ids.iter().cloned().collect::<Vec<_>>();
+        let mut templates = Vec::new();
+        for (key, template) in &self.fields {
+            let (parts, range) = parse_template(template)?;
+            ranges.extend(range);
+            templates.push((key, parts));
+        }
+        ranges.sort();
+        ranges.dedup();
+        let ids = match ranges.as_slice() {
+            [spec] => parse_ids(spec)?,
+            [] => return Err("target generator needs ids or a range like {1..80}".into()),
+            _ => return Err(format!("target generator has conflicting ids {:?}", ranges)),
+        };
+
+        let mut targets = Vec::new();
+        for id in ids {
+            if Some(id) == self.own_id {
+                continue;
+            }
+            let mut target = HashMap::new();
+            target.insert("IDX".to_string(), Value::from(id));
+            if let Some(name) = self.team_names.get(&id.to_string()) {
+                target.insert("NAME".to_string(), Value::from(name.clone()));
+            }
+            for (key, parts) in &templates {
+                let value = parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Literal(literal) => literal.clone(),
+                        Part::Id => id.to_string(),
+                    })
+                    .collect::<String>();
+                target.insert(key.to_string(), Value::from(value));
+            }
+            targets.push(target);
+        }
+        Ok(targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn expand(generator: Value) -> Result<Vec<HashMap<String, Value>>, String> {
+        serde_json::from_value::<TargetGenerator>(generator)
+            .unwrap()
+            .expand()
+    }
+
+    #[test]
+    fn inline_range() {
+        let targets = expand(json!({
+            "fields": { "IP": "10.60.{1..3}.2", "IPV6": "fd00::{id}:1" },
+            "team_names": { "2": "saarsec" },
+            "own_id": 3,
+        }))
+        .unwrap();
+        assert_eq!(
+            targets,
+            [
+                serde_json::from_value::<HashMap<String, Value>>(json!({
+                    "IDX": 1, "IP": "10.60.1.2", "IPV6": "fd00::1:1",
+                }))
+                .unwrap(),
+                serde_json::from_value(json!({
+                    "IDX": 2, "IP": "10.60.2.2", "IPV6": "fd00::2:1", "NAME": "saarsec",
+                }))
+                .unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn id_lists_and_escaping() {
+        let targets = expand(json!({
+            "ids": "5, 1..2, 2",
+            "fields": { "URL": "http://team{id}.ctf/{{flag}}" },
+        }))
+        .unwrap();
+        let urls = targets
+            .iter()
+            .map(|target| target["URL"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "http://team1.ctf/{flag}",
+                "http://team2.ctf/{flag}",
+                "http://team5.ctf/{flag}"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_generators() {
+        for generator in [
+            json!({ "fields": { "IP": "10.60.{id}.2" } }),
+            json!({ "fields": { "IP": "10.60.{1..3}.{1..4}" } }),
+            json!({ "ids": "1..x", "fields": { "IP": "10.60.{id}.2" } }),
+            json!({ "ids": "80..1", "fields": { "IP": "10.60.{id}.2" } }),
+            json!({ "ids": "1..3", "fields": { "IP": "10.60.{team}.2" } }),
+            json!({ "ids": "1..3", "fields": { "IP": "10.60.{id.2" } }),
+        ] {
+            assert!(expand(generator.clone()).is_err(), "{}", generator);
+        }
+    }
+}
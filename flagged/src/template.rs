@@ -0,0 +1,175 @@
+//! Command templates: `$IP`, `${IP}`, `${FLAG_IDS.user}`, `${PORT:-1337}` and `$$` for a literal `$`.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::config::value_to_string;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Variable {
+        path: Vec<String>,
+        default: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = source;
+        while let Some(idx) = rest.find('$') {
+            literal.push_str(&rest[..idx]);
+            rest = &rest[idx + 1..];
+
+            let variable = if let Some(braced) = rest.strip_prefix('{') {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| format!("unterminated ${{ in {:?}", source))?;
+                let (path, default) = match braced[..end].split_once(":-") {
+                    Some((path, default)) => (path, Some(default.to_string())),
+                    None => (&braced[..end], None),
+                };
+                let path = path.split('.').map(str::to_string).collect::<Vec<_>>();
+                if path
+                    .iter()
+                    .any(|segment| !segment.chars().all(is_ident) || segment.is_empty())
+                {
+                    return Err(format!(
+                        "invalid variable ${{{}}} in {:?}",
+                        &braced[..end],
+                        source
+                    ));
+                }
+                rest = &braced[end + 1..];
+                Part::Variable { path, default }
+            } else if let Some(escaped) = rest.strip_prefix('$') {
+                literal.push('$');
+                rest = escaped;
+                continue;
+            } else {
+                let end = rest.find(|c| !is_ident(c)).unwrap_or(rest.len());
+                if end == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+                    // a lone `$` (or `$5`) is kept as is
+                    literal.push('$');
+                    continue;
+                }
+                let path = vec![rest[..end].to_string()];
+                rest = &rest[end..];
+                Part::Variable {
+                    path,
+                    default: None,
+                }
+            };
+
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(variable);
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    pub fn render(&self, data: &HashMap<String, Value>) -> Result<String, String> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Variable { path, default } => {
+                    let value = path[1..]
+                        .iter()
+                        .try_fold(data.get(&path[0]), |value, segment| {
+                            Some(match value? {
+                                Value::Object(map) => map.get(segment),
+                                Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+                                _ => None,
+                            })
+                        })
+                        .flatten()
+                        .filter(|value| !value.is_null());
+                    match (value, default) {
+                        (Some(value), _) => rendered.push_str(&value_to_string(value)),
+                        (None, Some(default)) => rendered.push_str(default),
+                        (None, None) => {
+                            return Err(format!(
+                                "{:?} references missing field {}",
+                                self.source,
+                                path.join(".")
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &str) -> Result<String, String> {
+        let data = serde_json::from_value(json!({
+            "IP": "10.0.0.1",
+            "IPV6": "fd00::1",
+            "IDX": 3,
+            "NULL": null,
+            "FLAG_IDS": { "user": "alice", "notes": [17, 42] },
+        }))
+        .unwrap();
+        Template::parse(template)?.render(&data)
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(render("$IP $IPV6").unwrap(), "10.0.0.1 fd00::1");
+        assert_eq!(render("${IP}V6 ${IPV6}").unwrap(), "10.0.0.1V6 fd00::1");
+        assert_eq!(render("--idx=$IDX").unwrap(), "--idx=3");
+        assert_eq!(render("./$IP.sh").unwrap(), "./10.0.0.1.sh");
+        assert_eq!(render("${FLAG_IDS.user}").unwrap(), "alice");
+        assert_eq!(render("${FLAG_IDS.notes.1}").unwrap(), "42");
+        assert_eq!(
+            render("$FLAG_IDS").unwrap(),
+            r#"{"notes":[17,42],"user":"alice"}"#
+        );
+    }
+
+    #[test]
+    fn defaults_and_escaping() {
+        assert_eq!(render("${PORT:-1337}").unwrap(), "1337");
+        assert_eq!(render("${NULL:-}").unwrap(), "");
+        assert_eq!(render("${IP:-localhost}").unwrap(), "10.0.0.1");
+        assert_eq!(render("$$IP costs $5 $").unwrap(), "$IP costs $5 $");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(render("$PORT").is_err());
+        assert!(render("${FLAG_IDS.admin}").is_err());
+        assert!(render("${IP").is_err());
+        assert!(render("${}").is_err());
+        assert!(render("${IP..x}").is_err());
+        assert!(render("${IP-x}").is_err());
+    }
+}
@@ -0,0 +1,477 @@
+//! Runs full intervals against fake exploits (shell scripts) and a recording submitter.
+
+use crate::config::{Config, PrimaryKey, Target};
+use crate::ctfapi::{Flag, Submitter};
+use crate::events::{self, Event, EventPayload, SessionRunHandle};
+use crate::flaghandler::FlagHandler;
+use crate::proc::ProcessConfig;
+use crate::runner::Runner;
+use crate::scanner::{FlagMatcher, DEFAULT_MAX_LINE_LENGTH};
+use crate::submitter::{FlagBatcher, SubmissionPolicy};
+use crate::template::Template;
+
+use regex::bytes::Regex;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Mutex};
+
+type Batches = Arc<std::sync::Mutex<Vec<Vec<String>>>>;
+
+struct RecordingSubmitter {
+    batches: Batches,
+}
+
+impl Submitter for RecordingSubmitter {
+    fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
+        let flags = batch.iter().map(|flag| flag.to_string()).collect();
+        self.batches.lock().unwrap().push(flags);
+        for flag in batch {
+            flag.set_verdict("OK".into());
+        }
+        Ok(())
+    }
+}
+
+/// Answers two flags, then drops the connection. Retries answer everything but `FLAG{ignored}`,
+/// which the batcher then marks as unanswered.
+struct FlakySubmitter {
+    batches: Batches,
+}
+
+impl Submitter for FlakySubmitter {
+    fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
+        let mut batches = self.batches.lock().unwrap();
+        batches.push(batch.iter().map(|flag| flag.to_string()).collect());
+        if batches.len() == 1 {
+            for flag in &batch[..2] {
+                flag.set_verdict("OK".into());
+            }
+            return Err(std::io::ErrorKind::ConnectionReset.into());
+        }
+        for flag in batch.iter().filter(|flag| &***flag != "FLAG{ignored}") {
+            flag.set_verdict("OK".into());
+        }
+        Ok(())
+    }
+}
+
+/// Never gets through to the gameserver
+struct FailingSubmitter {
+    batches: Batches,
+}
+
+impl Submitter for FailingSubmitter {
+    fn submit_batch(&self, batch: &[Flag]) -> std::io::Result<()> {
+        let flags = batch.iter().map(|flag| flag.to_string()).collect();
+        self.batches.lock().unwrap().push(flags);
+        Err(std::io::ErrorKind::ConnectionRefused.into())
+    }
+}
+
+struct Harness {
+    _dir: tempfile::TempDir,
+    runner: Runner,
+    events: mpsc::UnboundedReceiver<Event>,
+    batches: Batches,
+}
+
+impl Harness {
+    /// Creates one target per exploit, keyed by name. Exploits are shell script bodies.
+    fn new(exploits: &[(&str, &str)]) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let command = vec!["sh".to_string(), "./$IP.sh".to_string()];
+        let mut targets = Vec::new();
+        for (key, script) in exploits {
+            std::fs::write(dir.path().join(format!("{}.sh", key)), script).unwrap();
+            let data = HashMap::from([("IP".to_string(), json!(key))]);
+            let cwd = dir.path().to_string_lossy().to_string();
+            let template = [
+                Template::parse(&command[0]).unwrap(),
+                Template::parse(&command[1]).unwrap(),
+            ];
+            targets.push(Arc::new(
+                Target::new(&PrimaryKey::default(), &template, &data, cwd).unwrap(),
+            ));
+        }
+
+        let config: Config = serde_json::from_value(json!({
+            "command": command,
+            "interval": 0.1,
+            "timeout": 5.0,
+            "concurrency": 4,
+            "targets": [],
+        }))
+        .unwrap();
+        let (tx, events) = mpsc::unbounded_channel();
+        let events_session = events::Session::open(
+            vec![events::Publisher::Channel(tx)],
+            events::SessionAnnouncement {
+                config: serde_json::to_value(&config).unwrap(),
+                hostname: "test".into(),
+                path: "test".into(),
+            },
+        );
+
+        let batches = Batches::default();
+        let flag_batcher = FlagBatcher::start(
+            Box::new(RecordingSubmitter {
+                batches: batches.clone(),
+            }),
+            SubmissionPolicy::default(),
+            None,
+            events_session.handle(),
+        );
+        let flag_handler = Arc::new(Mutex::new(FlagHandler::new(flag_batcher)));
+        let flag_regex = Regex::new(r"FLAG\{\w+\}").unwrap();
+        let process_config = ProcessConfig {
+            print_stdout: false,
+            print_stderr: false,
+            flag_matcher: Arc::new(FlagMatcher::new(&[flag_regex], Vec::new(), &[]).unwrap()),
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            flag_handler: flag_handler.clone(),
+            timeout: Duration::from_secs(5),
+        };
+
+        let runner = Runner {
+            interval: Duration::from_millis(100),
+            concurrency: 4,
+            targets,
+            process_config,
+            flag_handler,
+            events_session,
+            clock: None,
+            round: None,
+        };
+
+        Harness {
+            _dir: dir,
+            runner,
+            events,
+            batches,
+        }
+    }
+
+    /// Describes all events published so far. Verdicts are published asynchronously and skipped.
+    fn events(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            events.extend(describe(&event.payload));
+        }
+        events
+    }
+
+    fn events_for(&mut self, key: &str) -> Vec<String> {
+        self.events()
+            .into_iter()
+            .filter(|event| event.split(' ').nth(1) == Some(key))
+            .collect()
+    }
+
+    fn submitted(&self) -> Vec<String> {
+        self.batches.lock().unwrap().concat()
+    }
+}
+
+fn describe(payload: &EventPayload) -> Option<String> {
+    Some(match payload {
+        EventPayload::SessionAnnouncement(_) => "SessionAnnouncement".into(),
+        EventPayload::SessionEnd { intervals } => format!("SessionEnd {}", intervals),
+        EventPayload::IntervalStart(_) => "IntervalStart".into(),
+        EventPayload::IntervalEnd(_) => "IntervalEnd".into(),
+        EventPayload::RunSpawnFailed { run, .. } => format!("RunSpawnFailed {}", run.key),
+        EventPayload::RunKillFailed { run, .. } => format!("RunKillFailed {}", run.key),
+        EventPayload::RunStats { run, rusage, .. } => {
+            format!("RunStats {} rusage={}", run.key, rusage.is_some())
+        }
+        EventPayload::SubmitBatch {
+            flags,
+            verdicts,
+            error,
+            ..
+        } => format!("SubmitBatch {}/{} {:?}", verdicts, flags, error),
+        EventPayload::RunStart(run) => format!("RunStart {}", run.key),
+        EventPayload::RunTimeout(run) => format!("RunTimeout {}", run.key),
+        EventPayload::RunExit { run, exit_code } => format!("RunExit {} {:?}", run.key, exit_code),
+        EventPayload::StdoutLine { run, line } => format!("StdoutLine {} {}", run.key, line),
+        EventPayload::StderrLine { run, line } => format!("StderrLine {} {}", run.key, line),
+        EventPayload::FlagMatch {
+            run,
+            flag,
+            is_unique,
+        } => format!("FlagMatch {} {} {}", run.key, flag, is_unique),
+        EventPayload::FlagPending { run, flag } => format!("FlagPending {} {}", run.key, flag),
+        _ => return None,
+    })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn interval_event_sequence() {
+    let mut harness = Harness::new(&[("a", "echo hello\necho FLAG{a1}")]);
+    harness.runner.run_interval().await;
+
+    assert_eq!(
+        harness.events(),
+        [
+            "SessionAnnouncement",
+            "IntervalStart",
+            "RunStart a",
+            "StdoutLine a hello",
+            "StdoutLine a FLAG{a1}",
+            "FlagMatch a FLAG{a1} true",
+            "FlagPending a FLAG{a1}",
+            "RunExit a Some(0)",
+            "RunStats a rusage=true",
+            "SubmitBatch 1/1 None",
+            "IntervalEnd",
+        ]
+    );
+    assert_eq!(harness.submitted(), ["FLAG{a1}"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn flags_are_deduplicated_across_runs() {
+    let mut harness = Harness::new(&[
+        ("a", "echo FLAG{shared}"),
+        ("b", "echo FLAG{shared} FLAG{b1}"),
+    ]);
+    harness.runner.concurrency = 1;
+    harness.runner.run_interval().await;
+    harness.runner.run_interval().await;
+
+    let matches = harness
+        .events()
+        .into_iter()
+        .filter(|event| event.starts_with("FlagMatch"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        matches,
+        [
+            "FlagMatch a FLAG{shared} true",
+            "FlagMatch b FLAG{shared} false",
+            "FlagMatch b FLAG{b1} true",
+            "FlagMatch a FLAG{shared} false",
+            "FlagMatch b FLAG{shared} false",
+            "FlagMatch b FLAG{b1} false",
+        ]
+    );
+    // the batch is ordered freshest first
+    assert_eq!(harness.submitted(), ["FLAG{b1}", "FLAG{shared}"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batcher_respects_size_limit_and_flushes_everything() {
+    let batches = Batches::default();
+    let mut batcher = FlagBatcher::start(
+        Box::new(RecordingSubmitter {
+            batches: batches.clone(),
+        }),
+        SubmissionPolicy::default(),
+        None,
+        events::SessionHandle::noop(),
+    );
+    let run_handle = Arc::new(Mutex::new(SessionRunHandle::noop()));
+
+    let batch_size = SubmissionPolicy::default().batch_size;
+    let count = 2 * batch_size + 5;
+    for i in 0..count {
+        batcher
+            .submit(Flag::new(&format!("FLAG{{{}}}", i), "", &run_handle))
+            .await;
+    }
+    batcher.flush().await;
+    assert_eq!(batcher.queue_depth(), 0);
+
+    let batches = batches.lock().unwrap();
+    assert!(batches.len() >= 3);
+    assert!(batches.iter().all(|batch| batch.len() <= batch_size));
+    assert_eq!(batches.concat().len(), count);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batcher_skips_expired_flags_and_prefers_fresh_ones() {
+    let batches = Batches::default();
+    let mut batcher = FlagBatcher::start(
+        Box::new(RecordingSubmitter {
+            batches: batches.clone(),
+        }),
+        SubmissionPolicy::default(),
+        Some(Duration::from_secs(60)),
+        events::SessionHandle::noop(),
+    );
+    let run_handle = Arc::new(Mutex::new(SessionRunHandle::noop()));
+
+    // captured 55s, 45s, ..., 5s ago, followed by expired ones
+    for age in [55, 45, 35, 25, 15, 5, 65, 95] {
+        let mut flag = Flag::new(&format!("FLAG{{{}}}", age), "", &run_handle);
+        flag.captured_at = chrono::Utc::now() - chrono::Duration::seconds(age);
+        batcher.submit(flag).await;
+    }
+    batcher.flush().await;
+
+    assert_eq!(
+        batches.lock().unwrap().concat(),
+        ["FLAG{5}", "FLAG{15}", "FLAG{25}", "FLAG{35}", "FLAG{45}", "FLAG{55}"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batcher_retries_only_flags_without_verdicts() {
+    let batches = Batches::default();
+    let policy = SubmissionPolicy {
+        backoff_initial: 0.01,
+        ..Default::default()
+    };
+    let mut batcher = FlagBatcher::start(
+        Box::new(FlakySubmitter {
+            batches: batches.clone(),
+        }),
+        policy,
+        None,
+        events::SessionHandle::noop(),
+    );
+    let run_handle = Arc::new(Mutex::new(SessionRunHandle::noop()));
+
+    let mut flags = Vec::new();
+    // submitted freshest first
+    for (age, name) in ["a", "b", "ignored", "c"].iter().enumerate() {
+        let mut flag = Flag::new(&format!("FLAG{{{}}}", name), "", &run_handle);
+        flag.captured_at = chrono::Utc::now() - chrono::Duration::seconds(age as i64);
+        flags.push(flag);
+    }
+    for flag in flags.into_iter().rev() {
+        batcher.submit(flag).await;
+    }
+    batcher.flush().await;
+
+    assert_eq!(
+        *batches.lock().unwrap(),
+        [
+            vec!["FLAG{a}", "FLAG{b}", "FLAG{ignored}", "FLAG{c}"],
+            vec!["FLAG{ignored}", "FLAG{c}"],
+        ]
+    );
+    assert_eq!(batcher.queue_depth(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_exploits_are_killed() {
+    let mut harness = Harness::new(&[("slow", "echo FLAG{early}\nsleep 10")]);
+    harness.runner.process_config.timeout = Duration::from_millis(300);
+
+    let started_at = Instant::now();
+    harness.runner.run_interval().await;
+    assert!(started_at.elapsed() < Duration::from_secs(2));
+
+    assert_eq!(
+        harness.events_for("slow"),
+        [
+            "RunStart slow",
+            "StdoutLine slow FLAG{early}",
+            "FlagMatch slow FLAG{early} true",
+            "FlagPending slow FLAG{early}",
+            "RunTimeout slow",
+            "RunStats slow rusage=true",
+        ]
+    );
+    assert_eq!(harness.submitted(), ["FLAG{early}"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn crashing_exploits_report_exit_status() {
+    let mut harness = Harness::new(&[
+        ("fail", "echo broken >&2\nexit 3"),
+        ("crash", "echo FLAG{crash}\nkill -SEGV $$"),
+    ]);
+    harness.runner.run_interval().await;
+
+    let events = harness.events();
+    assert!(events.contains(&"StderrLine fail broken".to_string()));
+    assert!(events.contains(&"RunExit fail Some(3)".to_string()));
+    assert!(events.contains(&"RunExit crash None".to_string()));
+    assert_eq!(harness.submitted(), ["FLAG{crash}"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spawn_failures_are_reported() {
+    let mut harness = Harness::new(&[]);
+    let template = [Template::parse("/nonexistent/exploit").unwrap()];
+    let data = HashMap::from([("IP".to_string(), json!("missing"))]);
+    let target = Target::new(&PrimaryKey::default(), &template, &data, ".".into()).unwrap();
+    harness.runner.targets.push(Arc::new(target));
+    harness.runner.run_interval().await;
+
+    assert_eq!(harness.events_for("missing"), ["RunSpawnFailed missing"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runner_stops_while_snoozing() {
+    let mut harness = Harness::new(&[("a", "true")]);
+    harness.runner.interval = Duration::from_secs(60);
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let stop = async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        stop_tx.send(true).unwrap();
+    };
+    let run = tokio::time::timeout(Duration::from_secs(5), harness.runner.run(stop_rx));
+    let (res, ()) = tokio::join!(run, stop);
+    assert!(res.is_ok(), "runner did not stop");
+
+    let lifecycle = harness
+        .events()
+        .into_iter()
+        .filter(|event| event == "IntervalStart" || event.starts_with("SessionEnd"))
+        .collect::<Vec<_>>();
+    assert_eq!(lifecycle, ["IntervalStart", "SessionEnd 1"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batcher_stops_retrying_expired_flags() {
+    let batches = Batches::default();
+    let policy = SubmissionPolicy {
+        backoff_initial: 0.02,
+        backoff_max: 0.02,
+        ..Default::default()
+    };
+    let mut batcher = FlagBatcher::start(
+        Box::new(FailingSubmitter {
+            batches: batches.clone(),
+        }),
+        policy,
+        Some(Duration::from_millis(300)),
+        events::SessionHandle::noop(),
+    );
+    let run_handle = Arc::new(Mutex::new(SessionRunHandle::noop()));
+
+    batcher
+        .submit(Flag::new("FLAG{old}", "", &run_handle))
+        .await;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    batcher
+        .submit(Flag::new("FLAG{new}", "", &run_handle))
+        .await;
+    // only returns once both flags expired
+    tokio::time::timeout(Duration::from_secs(5), batcher.flush())
+        .await
+        .unwrap();
+    assert_eq!(batcher.queue_depth(), 0);
+
+    // nothing is submitted anymore afterwards
+    let attempted = batches.lock().unwrap().len();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let batches = batches.lock().unwrap().clone();
+    assert_eq!(batches.len(), attempted);
+    let attempts = |flag: &str| {
+        batches
+            .iter()
+            .filter(|b| b.iter().any(|f| f == flag))
+            .count()
+    };
+    assert!(attempts("FLAG{old}") > 1);
+    // the newer flag goes first once it's queued, and is retried after the old one expired
+    let both = batches.iter().position(|batch| batch.len() == 2).unwrap();
+    assert_eq!(batches[both], ["FLAG{new}", "FLAG{old}"]);
+    assert_eq!(batches.last().unwrap(), &["FLAG{new}"]);
+}
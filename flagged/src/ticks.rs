@@ -0,0 +1,289 @@
+//! Aligns intervals to the game's round clock, either from a known game start or a round-info endpoint.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+// How often the round-info endpoint is polled while waiting for a round change
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TickConfig {
+    /// Start of the first round, e.g. "2022-05-28T12:00:00Z"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<DateTime<Utc>>,
+    /// Round length in seconds
+    pub round_length: f64,
+    /// Seconds after each tick to start the interval at
+    #[serde(default)]
+    pub offset: f64,
+    /// Number of the round starting at `start`
+    #[serde(default = "default_first_round")]
+    pub first_round: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round_info: Option<RoundInfo>,
+}
+
+fn default_first_round() -> u64 {
+    1
+}
+
+/// HTTP endpoint returning the current round as JSON, e.g. `{"current_round": 42, "start": 1653739200}`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoundInfo {
+    /// Only plain http:// URLs are supported
+    pub url: String,
+    /// JSON pointer to the current round number
+    #[serde(default = "default_round_pointer")]
+    pub round: String,
+    /// JSON pointer to the current round's start (unix timestamp or RFC 3339). If missing,
+    /// the endpoint is polled until the round changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+}
+
+fn default_round_pointer() -> String {
+    "/current_round".into()
+}
+
+impl TickConfig {
+    pub fn round_length(&self) -> chrono::Duration {
+        chrono::Duration::milliseconds((self.round_length * 1000.0) as i64)
+    }
+
+    pub fn offset(&self) -> chrono::Duration {
+        chrono::Duration::milliseconds((self.offset * 1000.0) as i64)
+    }
+}
+
+async fn http_get_json(url: &str) -> Result<Value, String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported round-info URL {:?}, expected http://", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let request = async {
+        let mut stream = TcpStream::connect(&addr).await?;
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+            path, host
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(HTTP_TIMEOUT, request)
+        .await
+        .map_err(|_| format!("{}: timed out", url))?
+        .map_err(|err| format!("{}: {}", url, err))?;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| format!("{}: malformed HTTP response", url))?;
+    let status = head.lines().next().unwrap_or("");
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(format!("{}: unexpected status {:?}", url, status));
+    }
+    serde_json::from_str(body).map_err(|err| format!("{}: {}", url, err))
+}
+
+fn parse_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Number(n) => {
+            let millis = (n.as_f64()? * 1000.0) as i64;
+            Utc.timestamp_millis_opt(millis).single()
+        }
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|date| date.with_timezone(&Utc)),
+        _ => None,
+    }
+}
+
+impl RoundInfo {
+    /// Fetches the current round and, if configured, its start.
+    async fn fetch(&self) -> Result<(u64, Option<DateTime<Utc>>), String> {
+        let info = http_get_json(&self.url).await?;
+        let round = info
+            .pointer(&self.round)
+            .and_then(|round| round.as_u64().or_else(|| round.as_str()?.parse().ok()))
+            .ok_or_else(|| format!("{}: no round number at {:?}", self.url, self.round))?;
+        let start = match &self.start {
+            Some(pointer) => Some(
+                info.pointer(pointer)
+                    .and_then(parse_timestamp)
+                    .ok_or_else(|| format!("{}: no round start at {:?}", self.url, pointer))?,
+            ),
+            None => None,
+        };
+        Ok((round, start))
+    }
+}
+
+pub struct Clock {
+    config: TickConfig,
+    // A known round and when it started
+    anchor: Option<(u64, DateTime<Utc>)>,
+}
+
+impl Clock {
+    pub fn new(config: TickConfig) -> Clock {
+        let anchor = config.start.map(|start| (config.first_round, start));
+        Clock { config, anchor }
+    }
+
+    /// Re-synchronizes with the round-info endpoint, if there is one.
+    async fn calibrate(&mut self) -> Result<(), String> {
+        let info = match &self.config.round_info {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+        let (round, start) = info.fetch().await?;
+        if let Some(start) = start {
+            self.anchor = Some((round, start));
+        } else if self.anchor.is_none() {
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                let (current, _) = info.fetch().await?;
+                if current != round {
+                    self.anchor = Some((current, Utc::now()));
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn round_at(&self, time: DateTime<Utc>) -> Option<u64> {
+        let (round, start) = self.anchor?;
+        let length = self.config.round_length().num_milliseconds().max(1);
+        let elapsed = (time - start).num_milliseconds();
+        Some((round as i64 + elapsed.div_euclid(length)).max(0) as u64)
+    }
+
+    fn round_start(&self, round: u64) -> Option<DateTime<Utc>> {
+        let (anchor_round, start) = self.anchor?;
+        let rounds = i64::try_from(round).ok()? - i64::try_from(anchor_round).ok()?;
+        let offset = self
+            .config
+            .round_length()
+            .num_milliseconds()
+            .checked_mul(rounds)?;
+        start.checked_add_signed(chrono::Duration::milliseconds(offset))
+    }
+
+    /// The current round number, if known.
+    pub async fn current_round(&mut self) -> Result<u64, String> {
+        self.calibrate().await?;
+        self.round_at(Utc::now())
+            .ok_or_else(|| "tick alignment needs a start time or round-info endpoint".into())
+    }
+
+    /// Returns the next round whose interval (tick + offset) hasn't started yet and when to start it.
+    pub async fn next_tick(&mut self) -> Result<(u64, DateTime<Utc>), String> {
+        self.calibrate().await?;
+        let now = Utc::now();
+        let mut round = self
+            .round_at(now - self.config.offset())
+            .ok_or("tick alignment needs a start time or round-info endpoint")?;
+        loop {
+            let start = self
+                .round_start(round)
+                .ok_or_else(|| format!("round {} is out of range", round))?
+                + self.config.offset();
+            if start > now {
+                return Ok((round, start));
+            }
+            round += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    fn make_clock(config: Value) -> Clock {
+        Clock::new(serde_json::from_value(config).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ticks_from_game_start() {
+        let start = Utc::now() - chrono::Duration::seconds(250);
+        let mut clock = make_clock(json!({ "start": start, "round_length": 100, "offset": 10 }));
+        assert_eq!(clock.current_round().await.unwrap(), 3);
+
+        // round 3's interval started 40s ago
+        let (round, at) = clock.next_tick().await.unwrap();
+        assert_eq!(round, 4);
+        assert_eq!(at, start + chrono::Duration::seconds(310));
+
+        // with a larger offset, this round's interval is still ahead
+        let mut clock = make_clock(json!({ "start": start, "round_length": 100, "offset": 60 }));
+        let (round, at) = clock.next_tick().await.unwrap();
+        assert_eq!(round, 3);
+        assert_eq!(at, start + chrono::Duration::seconds(260));
+    }
+
+    #[test]
+    fn large_round_numbers() {
+        let start = Utc::now();
+        let mut clock = make_clock(json!({ "round_length": 60 }));
+        clock.anchor = Some((3_000_000_000, start));
+        assert_eq!(
+            clock.round_start(3_000_000_001),
+            Some(start + chrono::Duration::seconds(60))
+        );
+        assert_eq!(
+            clock.round_start(0),
+            Some(start - chrono::Duration::seconds(180_000_000_000))
+        );
+        assert_eq!(clock.round_start(u64::MAX), None);
+    }
+
+    #[tokio::test]
+    async fn ticks_from_round_info() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let round_start = Utc::now().timestamp() - 30;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let body = json!({ "round": { "id": 17, "start": round_start } }).to_string();
+                let response = format!("HTTP/1.0 200 OK\r\n\r\n{}", body);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let mut clock = make_clock(json!({
+            "round_length": 60,
+            "round_info": {
+                "url": format!("http://{}/api/round", addr),
+                "round": "/round/id",
+                "start": "/round/start",
+            },
+        }));
+        assert_eq!(clock.current_round().await.unwrap(), 17);
+        let (round, at) = clock.next_tick().await.unwrap();
+        assert_eq!(round, 18);
+        assert_eq!(at.timestamp(), round_start + 60);
+    }
+}
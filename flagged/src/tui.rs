@@ -0,0 +1,652 @@
+//! `--tui`: a live dashboard of all targets instead of scrolling output.
+//!
+//! stdout and stderr are redirected into the log pane while the dashboard is open,
+//! so everything printed by the runner, submitter and sinks ends up there.
+
+use crate::ctfapi::VerdictClass;
+use crate::events::{Event, EventPayload};
+use crate::metrics::metrics;
+use crate::runner::Runner;
+
+use chrono::{DateTime, Utc};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::crossterm::{cursor, execute};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+const LOG_HEIGHT: u16 = 10;
+const MAX_LOG_LINES: usize = 1000;
+const MAX_OUTPUT_LINES: usize = 500;
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Duplicates `fd` with close-on-exec set, so exploits don't inherit it
+fn dup(fd: RawFd) -> io::Result<File> {
+    let fd = check(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) })?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// stdout and stderr redirected into a pipe. The original stdout is kept to draw the dashboard.
+pub struct Capture {
+    terminal: File,
+    stderr: File,
+    lines: mpsc::UnboundedReceiver<String>,
+}
+
+/// Redirects stdout and stderr until the returned `Capture` is restored.
+pub fn capture_output() -> io::Result<Capture> {
+    io::stdout().flush()?;
+    let terminal = dup(libc::STDOUT_FILENO)?;
+    let stderr = dup(libc::STDERR_FILENO)?;
+
+    let mut fds = [0; 2];
+    check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    check(unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    check(unsafe { libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO) })?;
+    check(unsafe { libc::dup2(writer.as_raw_fd(), libc::STDERR_FILENO) })?;
+    drop(writer);
+
+    let (tx, lines) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        // ends once stdout and stderr are restored
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        while let Ok(n) = reader.read_until(b'\n', &mut line) {
+            if n == 0 {
+                break;
+            }
+            let text = strip_ansi(String::from_utf8_lossy(&line).trim_end());
+            if tx.send(text).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    });
+
+    Ok(Capture {
+        terminal,
+        stderr,
+        lines,
+    })
+}
+
+impl Capture {
+    fn restore(&self) {
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::dup2(self.terminal.as_raw_fd(), libc::STDOUT_FILENO);
+            libc::dup2(self.stderr.as_raw_fd(), libc::STDERR_FILENO);
+        }
+    }
+}
+
+/// Removes color codes, which would be garbage inside the dashboard
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            // parameters until the final byte
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Raw mode and the alternate screen. Everything is restored on drop, even when panicking.
+struct Screen {
+    terminal: ratatui::Terminal<CrosstermBackend<File>>,
+    capture: Capture,
+}
+
+impl Screen {
+    fn open(capture: Capture) -> io::Result<Self> {
+        let mut out = capture.terminal.try_clone()?;
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, cursor::Hide)?;
+        let terminal = ratatui::Terminal::new(CrosstermBackend::new(out))?;
+        Ok(Screen { terminal, capture })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(
+            self.terminal.backend_mut(),
+            LeaveAlternateScreen,
+            cursor::Show
+        );
+        self.capture.restore();
+    }
+}
+
+enum RunState {
+    Idle,
+    Running,
+    Exited(Option<i32>),
+    TimedOut,
+}
+
+struct TargetState {
+    key: String,
+    state: RunState,
+    started_at: Option<DateTime<Utc>>,
+    duration: Option<Duration>,
+    /// Flags found in the current interval
+    flags: usize,
+    last_verdict: Option<String>,
+    /// Output of the latest run, `true` for stderr lines
+    output: VecDeque<(bool, String)>,
+}
+
+impl TargetState {
+    fn new(key: String) -> Self {
+        TargetState {
+            key,
+            state: RunState::Idle,
+            started_at: None,
+            duration: None,
+            flags: 0,
+            last_verdict: None,
+            output: VecDeque::new(),
+        }
+    }
+
+    fn finish(&mut self, state: RunState, at: DateTime<Utc>) {
+        self.state = state;
+        self.duration = self
+            .started_at
+            .and_then(|started_at| (at - started_at).to_std().ok());
+    }
+
+    fn push_output(&mut self, is_stderr: bool, line: String) {
+        if self.output.len() == MAX_OUTPUT_LINES {
+            self.output.pop_front();
+        }
+        self.output.push_back((is_stderr, line));
+    }
+}
+
+#[derive(PartialEq)]
+enum View {
+    Targets,
+    Output,
+}
+
+enum Action {
+    Redraw,
+    Stop,
+    Quit,
+}
+
+struct App {
+    targets: Vec<TargetState>,
+    index: HashMap<String, usize>,
+    table: TableState,
+    view: View,
+    interval_length: Duration,
+    interval: u64,
+    interval_started_at: Option<Instant>,
+    log: VecDeque<String>,
+    /// Lines the log pane is scrolled up by
+    log_scroll: usize,
+    stopping: bool,
+}
+
+impl App {
+    fn new(runner: &Runner) -> Self {
+        let targets = runner
+            .targets
+            .iter()
+            .map(|target| TargetState::new(target.key.clone()))
+            .collect::<Vec<_>>();
+        let index = targets
+            .iter()
+            .enumerate()
+            .map(|(idx, target)| (target.key.clone(), idx))
+            .collect();
+        App {
+            targets,
+            index,
+            table: TableState::default().with_selected(Some(0)),
+            view: View::Targets,
+            interval_length: runner.interval,
+            interval: 0,
+            interval_started_at: None,
+            log: VecDeque::new(),
+            log_scroll: 0,
+            stopping: false,
+        }
+    }
+
+    fn target(&mut self, key: &str) -> Option<&mut TargetState> {
+        let idx = *self.index.get(key)?;
+        self.targets.get_mut(idx)
+    }
+
+    fn record(&mut self, event: Event) {
+        let at = event.timestamp;
+        match event.payload {
+            EventPayload::IntervalStart(interval) => {
+                self.interval = interval.map_or(self.interval + 1, |interval| interval.index);
+                self.interval_started_at = Some(Instant::now());
+                for target in self.targets.iter_mut() {
+                    target.flags = 0;
+                }
+            }
+            EventPayload::RunStart(run) => {
+                if let Some(target) = self.target(&run.key) {
+                    target.state = RunState::Running;
+                    target.started_at = Some(at);
+                    target.output.clear();
+                }
+            }
+            EventPayload::RunExit { run, exit_code } => {
+                if let Some(target) = self.target(&run.key) {
+                    target.finish(RunState::Exited(exit_code), at);
+                }
+            }
+            EventPayload::RunTimeout(run) => {
+                if let Some(target) = self.target(&run.key) {
+                    target.finish(RunState::TimedOut, at);
+                }
+            }
+            EventPayload::StdoutLine { run, line } => {
+                if let Some(target) = self.target(&run.key) {
+                    target.push_output(false, line);
+                }
+            }
+            EventPayload::StderrLine { run, line } => {
+                if let Some(target) = self.target(&run.key) {
+                    target.push_output(true, line);
+                }
+            }
+            EventPayload::FlagMatch { run, .. } => {
+                if let Some(target) = self.target(&run.key) {
+                    target.flags += 1;
+                }
+            }
+            EventPayload::FlagVerdict { run, verdict, .. } => {
+                if let Some(target) = self.target(&run.key) {
+                    target.last_verdict = Some(verdict);
+                }
+            }
+            EventPayload::FlagDropped { run, reason, .. } => {
+                if let Some(target) = self.target(&run.key) {
+                    target.last_verdict = Some(format!("not submitted: {}", reason));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn log(&mut self, line: String) {
+        if self.log.len() == MAX_LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+        // keep the scrolled-to lines in place
+        if self.log_scroll > 0 {
+            self.log_scroll = (self.log_scroll + 1).min(self.log.len());
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Action {
+        let selected = self.table.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return self.quit()
+            }
+            KeyCode::Char('q') => return self.quit(),
+            KeyCode::Esc if self.view == View::Output => self.view = View::Targets,
+            KeyCode::Esc => return self.quit(),
+            KeyCode::Enter if self.view == View::Output => self.view = View::Targets,
+            KeyCode::Enter if !self.targets.is_empty() => self.view = View::Output,
+            KeyCode::Up | KeyCode::Char('k') => self.table.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') => self.table.select(Some(
+                (selected + 1).min(self.targets.len().saturating_sub(1)),
+            )),
+            KeyCode::PageUp => {
+                self.log_scroll = (self.log_scroll + LOG_HEIGHT as usize / 2).min(self.log.len())
+            }
+            KeyCode::PageDown => {
+                self.log_scroll = self.log_scroll.saturating_sub(LOG_HEIGHT as usize / 2)
+            }
+            KeyCode::End => self.log_scroll = 0,
+            _ => {}
+        }
+        Action::Redraw
+    }
+
+    /// The first request lets the current interval finish, the second one quits immediately
+    fn quit(&mut self) -> Action {
+        if self.stopping {
+            return Action::Quit;
+        }
+        self.stopping = true;
+        self.log("Stopping after the current interval, press q again to quit now".into());
+        Action::Stop
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, main, log] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(LOG_HEIGHT),
+        ])
+        .areas(frame.area());
+        self.draw_header(frame, header);
+        match self.view {
+            View::Targets => self.draw_targets(frame, main),
+            View::Output => self.draw_output(frame, main),
+        }
+        self.draw_log(frame, log);
+    }
+
+    fn draw_header(&self, frame: &mut Frame, area: Rect) {
+        let queue_depth = metrics().queue_depth.get(&[]);
+        let (ratio, progress) = match self.interval_started_at {
+            Some(started_at) => {
+                let elapsed = started_at.elapsed();
+                let ratio = elapsed.as_secs_f64() / self.interval_length.as_secs_f64();
+                let progress = format!(
+                    "interval {} | {:.0?} / {:.0?}",
+                    self.interval, elapsed, self.interval_length
+                );
+                (ratio.clamp(0.0, 1.0), progress)
+            }
+            None => (0.0, "waiting for the first interval".into()),
+        };
+        let mut label = format!("{} | {} flag(s) queued", progress, queue_depth);
+        if self.stopping {
+            label.push_str(" | stopping...");
+        }
+        let title = " flagged | q: quit, ↑↓: select, enter: output, pgup/pgdn: scroll log ";
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(title))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(if ratio.is_nan() { 1.0 } else { ratio })
+            .label(label);
+        frame.render_widget(gauge, area);
+    }
+
+    fn draw_targets(&mut self, frame: &mut Frame, area: Rect) {
+        let now = Utc::now();
+        let rows = self.targets.iter().map(|target| {
+            let (state, color) = match target.state {
+                RunState::Idle => ("idle".to_string(), Color::DarkGray),
+                RunState::Running => ("running".to_string(), Color::Cyan),
+                RunState::Exited(Some(0)) => ("exit 0".to_string(), Color::Green),
+                RunState::Exited(Some(code)) => (format!("exit {}", code), Color::Red),
+                RunState::Exited(None) => ("signal".to_string(), Color::Red),
+                RunState::TimedOut => ("timeout".to_string(), Color::Red),
+            };
+            let duration = match (&target.state, target.started_at) {
+                (RunState::Running, Some(started_at)) => (now - started_at).to_std().ok(),
+                _ => target.duration,
+            };
+            let verdict = match &target.last_verdict {
+                Some(verdict) => {
+                    let color = match VerdictClass::of(verdict) {
+                        VerdictClass::Accepted => Color::Green,
+                        VerdictClass::Unknown => Color::Yellow,
+                        _ => Color::Red,
+                    };
+                    Span::styled(verdict.clone(), Style::default().fg(color))
+                }
+                None => Span::raw(""),
+            };
+            Row::new(vec![
+                Span::raw(target.key.clone()),
+                Span::styled(state, Style::default().fg(color)),
+                Span::raw(
+                    duration
+                        .map(|duration| format!("{:.1?}", duration))
+                        .unwrap_or_default(),
+                ),
+                Span::raw(target.flags.to_string()),
+                verdict,
+            ])
+        });
+        let widths = [
+            Constraint::Min(16),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Fill(2),
+        ];
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(["target", "state", "duration", "flags", "last verdict"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::bordered().title(format!(" {} targets ", self.targets.len())))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn draw_output(&self, frame: &mut Frame, area: Rect) {
+        let target = match self.table.selected().and_then(|idx| self.targets.get(idx)) {
+            Some(target) => target,
+            None => return,
+        };
+        let lines = target
+            .output
+            .iter()
+            .map(|(is_stderr, line)| {
+                let style = match is_stderr {
+                    true => Style::default().fg(Color::Red),
+                    false => Style::default(),
+                };
+                Line::styled(line.clone(), style)
+            })
+            .collect::<Vec<_>>();
+        // follow the end of the output
+        let scroll = lines
+            .len()
+            .saturating_sub(area.height.saturating_sub(2) as usize);
+        let title = format!(" {}: latest run (stderr in red), esc: back ", target.key);
+        let output = Paragraph::new(lines)
+            .block(Block::bordered().title(title))
+            .scroll((scroll.min(u16::MAX as usize) as u16, 0));
+        frame.render_widget(output, area);
+    }
+
+    fn draw_log(&self, frame: &mut Frame, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let end = self.log.len() - self.log_scroll;
+        let lines = self
+            .log
+            .range(end.saturating_sub(height)..end)
+            .map(|line| Line::raw(line.clone()))
+            .collect::<Vec<_>>();
+        let title = match self.log_scroll {
+            0 => " log ".to_string(),
+            n => format!(" log, {} line(s) below (end: follow) ", n),
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+}
+
+fn read_keys(tx: mpsc::UnboundedSender<KeyEvent>) {
+    while let Ok(event) = event::read() {
+        if let event::Event::Key(key) = event {
+            if key.kind == KeyEventKind::Press && tx.send(key).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Runs intervals until the user quits, showing the dashboard on the terminal `capture` kept.
+pub async fn run(
+    mut runner: Runner,
+    mut events: mpsc::UnboundedReceiver<Event>,
+    capture: Capture,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut app = App::new(&runner);
+    let mut screen = Screen::open(capture)?;
+    let (keys_tx, mut keys) = mpsc::unbounded_channel();
+    std::thread::spawn(move || read_keys(keys_tx));
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+    let finished = {
+        let run = runner.run(stop_rx);
+        tokio::pin!(run);
+        loop {
+            screen.terminal.draw(|frame| app.draw(frame))?;
+            tokio::select! {
+                _ = &mut run => break true,
+                Some(event) = events.recv() => {
+                    app.record(event);
+                    while let Ok(event) = events.try_recv() {
+                        app.record(event);
+                    }
+                }
+                Some(line) = screen.capture.lines.recv() => {
+                    app.log(line);
+                    while let Ok(line) = screen.capture.lines.try_recv() {
+                        app.log(line);
+                    }
+                }
+                Some(key) = keys.recv() => match app.key(key) {
+                    Action::Redraw => {}
+                    Action::Stop => {
+                        let _ = stop_tx.send(true);
+                    }
+                    Action::Quit => break false,
+                },
+                _ = redraw.tick() => {}
+            }
+        }
+    };
+    if !finished {
+        // the interval was cut short, consumers still get to know the session is over
+        runner.events_session.end();
+        runner.events_session.flush().await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Run;
+    use uuid::Uuid;
+
+    fn event(payload: EventPayload) -> Event {
+        Event {
+            version: crate::events::SCHEMA_VERSION,
+            session_id: Uuid::nil(),
+            timestamp: Utc::now(),
+            payload,
+        }
+    }
+
+    fn run(key: &str) -> Run {
+        Run {
+            id: Uuid::nil(),
+            key: key.into(),
+            target: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn strips_color_codes() {
+        assert_eq!(strip_ansi("\x1b[36m10.0.0.1\x1b[0m | hi"), "10.0.0.1 | hi");
+        assert_eq!(strip_ansi("plain"), "plain");
+    }
+
+    #[test]
+    fn tracks_runs_per_target() {
+        let mut app = App {
+            targets: vec![TargetState::new("a".into()), TargetState::new("b".into())],
+            index: [("a".to_string(), 0), ("b".to_string(), 1)]
+                .iter()
+                .cloned()
+                .collect(),
+            table: TableState::default(),
+            view: View::Targets,
+            interval_length: Duration::from_secs(10),
+            interval: 0,
+            interval_started_at: None,
+            log: VecDeque::new(),
+            log_scroll: 0,
+            stopping: false,
+        };
+        app.record(event(EventPayload::IntervalStart(None)));
+        app.record(event(EventPayload::RunStart(run("b"))));
+        app.record(event(EventPayload::StdoutLine {
+            run: run("b"),
+            line: "FLAG{1}".into(),
+        }));
+        app.record(event(EventPayload::FlagMatch {
+            run: run("b"),
+            flag: "FLAG{1}".into(),
+            is_unique: true,
+        }));
+        app.record(event(EventPayload::FlagVerdict {
+            run: run("b"),
+            flag: "FLAG{1}".into(),
+            verdict: "[OK]".into(),
+        }));
+        app.record(event(EventPayload::RunExit {
+            run: run("b"),
+            exit_code: Some(1),
+        }));
+        app.record(event(EventPayload::RunStart(run("unknown"))));
+
+        assert_eq!(app.interval, 1);
+        assert!(matches!(app.targets[0].state, RunState::Idle));
+        let b = &app.targets[1];
+        assert!(matches!(b.state, RunState::Exited(Some(1))));
+        assert!(b.duration.is_some());
+        assert_eq!(b.flags, 1);
+        assert_eq!(b.last_verdict.as_deref(), Some("[OK]"));
+        assert_eq!(b.output.len(), 1);
+
+        // output is replaced by the next run, flags are counted per interval
+        app.record(event(EventPayload::IntervalStart(None)));
+        app.record(event(EventPayload::RunStart(run("b"))));
+        assert_eq!(app.targets[1].flags, 0);
+        assert!(app.targets[1].output.is_empty());
+        assert_eq!(app.targets[1].last_verdict.as_deref(), Some("[OK]"));
+
+        // q stops after the interval, the second q quits
+        let q = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert!(matches!(app.key(q), Action::Stop));
+        assert!(matches!(app.key(q), Action::Quit));
+    }
+}
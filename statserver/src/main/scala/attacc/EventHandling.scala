@@ -48,16 +48,17 @@ object EventHandling {
           session <- Session.create(event.sessionId, hostname, path, config, event.timestamp)
           _       <- state.sessions.put(event.sessionId, Some(session))
         } yield ()
-      case IntervalStart() =>
+      case SessionEnd(intervals) => log.info(s"Session ended: ${event.sessionId} after $intervals interval(s): ${event.timestamp}")
+      case IntervalStart(info) =>
         for {
           session  <- getSession
-          uuid     <- Util.randomUUID
+          uuid     <- info.fold(Util.randomUUID)(info => ZIO.succeed(info.id))
           _        <- log.info(s"New interval: $uuid for session ${event.sessionId}: ${event.timestamp}")
           interval <- Interval.create(uuid, session.id, event.timestamp)
           _        <- session.intervals.put(uuid, Some(interval))
           _        <- session.activeInterval.put(Some(uuid))
         } yield ()
-      case IntervalEnd() =>
+      case IntervalEnd(_) =>
         for {
           session        <- getSession
           activeInterval <- getActiveInterval(session)
@@ -103,6 +104,12 @@ object EventHandling {
         } yield ()
       case FlagMatch(run, flag, isUnique) => ZIO.succeed(())
       case FlagPending(run, flag)         => ZIO.succeed(())
+      case RunSpawnFailed(run, error)     => log.warn(s"Run ${run.id} for ${run.key} failed to spawn: $error")
+      case RunKillFailed(run, error)      => log.warn(s"Run ${run.id} for ${run.key} couldn't be killed: $error")
+      case RunStats(run, wallTime, rusage) => ZIO.succeed(())
+      case SubmitBatch(flags, verdicts, duration, error) => ZIO.succeed(())
+      case FlagDropped(run, flag, reason) => ZIO.succeed(())
+      case UnknownEvent(t)                => log.debug(s"Ignoring unknown event $t")
       case FlagVerdict(run, flag, verdict) =>
         ZIO.when(verdict.contains("accepted"))(for {
           session <- getSession
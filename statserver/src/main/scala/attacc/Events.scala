@@ -13,6 +13,10 @@ import zio.ZIO
 
 final case class Run(id: UUID, key: String, target: Map[String, String])
 
+final case class IntervalInfo(id: UUID, index: Long, round: Option[Long])
+
+final case class ResourceUsage(userTime: Double, systemTime: Double, maxRssKb: Long)
+
 final case class Config(
   command: Array[String],
   interval: Double,
@@ -22,9 +26,12 @@ final case class Config(
 
 sealed trait EventPayload
 final case class SessionAnnouncement(hostname: String, path: String, config: Config) extends EventPayload
-final case class IntervalStart()                                                     extends EventPayload
-final case class IntervalEnd()                                                       extends EventPayload
+final case class SessionEnd(intervals: Long)                                         extends EventPayload
+final case class IntervalStart(info: Option[IntervalInfo])                           extends EventPayload
+final case class IntervalEnd(info: Option[IntervalInfo])                             extends EventPayload
 final case class RunStart(run: Run)                                                  extends EventPayload
+final case class RunSpawnFailed(run: Run, error: String)                             extends EventPayload
+final case class RunKillFailed(run: Run, error: String)                              extends EventPayload
 final case class RunTimeout(run: Run)                                                extends EventPayload
 final case class RunExit(run: Run, exitCode: Int)                                    extends EventPayload
 final case class StdoutLine(run: Run, line: String)                                  extends EventPayload
@@ -32,6 +39,12 @@ final case class StderrLine(run: Run, line: String)
 final case class FlagMatch(run: Run, flag: String, isUnique: Boolean)                extends EventPayload
 final case class FlagPending(run: Run, flag: String)                                 extends EventPayload
 final case class FlagVerdict(run: Run, flag: String, verdict: String)                extends EventPayload
+// given up without a verdict from the gameserver, reason is "expired" or "unanswered"
+final case class FlagDropped(run: Run, flag: String, reason: String)                 extends EventPayload
+final case class RunStats(run: Run, wallTime: Double, rusage: Option[ResourceUsage]) extends EventPayload
+final case class SubmitBatch(flags: Long, verdicts: Long, duration: Double, error: Option[String]) extends EventPayload
+// event types added by newer versions of flagged
+final case class UnknownEvent(t: String)                                             extends EventPayload
 
 final case class Event(sessionId: UUID, timestamp: LocalDateTime, payload: EventPayload) {
   def json: Json = this.asInstanceOf[Event].asJson(Events.encodeEvent)
@@ -45,9 +58,12 @@ object Events {
 
   implicit val encodeEventPayload: Encoder[EventPayload] = Encoder.instance {
     case e: SessionAnnouncement => wrapPayload("SessionAnnouncement", e)
-    case _: IntervalStart       => wrapPayload("IntervalStart")
-    case _: IntervalEnd         => wrapPayload("IntervalEnd")
+    case e: SessionEnd          => wrapPayload("SessionEnd", e)
+    case e: IntervalStart       => e.info.fold(wrapPayload("IntervalStart"))(wrapPayload("IntervalStart", _))
+    case e: IntervalEnd         => e.info.fold(wrapPayload("IntervalEnd"))(wrapPayload("IntervalEnd", _))
     case e: RunStart            => wrapPayload("RunStart", e.run)
+    case e: RunSpawnFailed      => wrapPayload("RunSpawnFailed", e)
+    case e: RunKillFailed       => wrapPayload("RunKillFailed", e)
     case e: RunTimeout          => wrapPayload("RunTimeout", e.run)
     case e: RunExit             => wrapPayload("RunExit", e)
     case e: StdoutLine          => wrapPayload("StdoutLine", e)
@@ -55,6 +71,10 @@ object Events {
     case e: FlagMatch           => wrapPayload("FlagMatch", e)
     case e: FlagPending         => wrapPayload("FlagPending", e)
     case e: FlagVerdict         => wrapPayload("FlagVerdict", e)
+    case e: FlagDropped         => wrapPayload("FlagDropped", e)
+    case e: RunStats            => wrapPayload("RunStats", e)
+    case e: SubmitBatch         => wrapPayload("SubmitBatch", e)
+    case e: UnknownEvent        => wrapPayload(e.t)
   }
 
   implicit val decodeEventPayload: Decoder[EventPayload] = Decoder.instance { cursor =>
@@ -63,12 +83,16 @@ object Events {
       w <- cursor.as[JsonObject]
       t <- w("t").toRight(error("field \"t\" required on wrapped EventPayload")).flatMap(_.as[String])
       requireC   = (f: Json => Decoder.Result[EventPayload]) => w("c").toRight(error(s"field c required on wrapped $t")).flatMap(f)
-      requireNoC = (e: EventPayload) => Either.cond(!w.contains("c"), e, error(s"field c not allowed on wrapped $t"))
+      // version 1 events don't carry a c
+      optionalC = (f: Json => Decoder.Result[EventPayload]) => w("c").filterNot(_.isNull).fold(f(Json.Null))(f)
       result <- t match {
         case "SessionAnnouncement" => requireC(_.as[SessionAnnouncement])
-        case "IntervalStart"       => requireNoC(IntervalStart())
-        case "IntervalEnd"         => requireNoC(IntervalEnd())
+        case "SessionEnd"          => requireC(_.as[SessionEnd])
+        case "IntervalStart"       => optionalC(_.as[Option[IntervalInfo]].map(IntervalStart))
+        case "IntervalEnd"         => optionalC(_.as[Option[IntervalInfo]].map(IntervalEnd))
         case "RunStart"            => requireC(_.as[Run].map(RunStart))
+        case "RunSpawnFailed"      => requireC(_.as[RunSpawnFailed])
+        case "RunKillFailed"       => requireC(_.as[RunKillFailed])
         case "RunTimeout"          => requireC(_.as[Run].map(RunTimeout))
         case "RunExit"             => requireC(_.as[RunExit])
         case "StdoutLine"          => requireC(_.as[StdoutLine])
@@ -76,6 +100,10 @@ object Events {
         case "FlagMatch"           => requireC(_.as[FlagMatch])
         case "FlagPending"         => requireC(_.as[FlagPending])
         case "FlagVerdict"         => requireC(_.as[FlagVerdict])
+        case "FlagDropped"         => requireC(_.as[FlagDropped])
+        case "RunStats"            => requireC(_.as[RunStats])
+        case "SubmitBatch"         => requireC(_.as[SubmitBatch])
+        case other                 => Right(UnknownEvent(other))
       }
     } yield result
   }